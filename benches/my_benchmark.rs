@@ -1,29 +1,33 @@
-use cbor::{decode_cbor, Cbor};
+use cbor::{decode_cbor, Cbor, EncodeOptions};
 use criterion::{criterion_group, criterion_main, Criterion};
 use ezcbor::*;
-use rand::rngs::ThreadRng;
 
 fn my_benchmark(c: &mut Criterion) {
 
     let mut group = c.benchmark_group("All benchmarks");
-    
-    
+
+
     let mut large_vec = Vec::new();
-    let rng = rand::thread_rng();
     for i in 0..1_000_000 {
         large_vec.push(i);
     }
 
     group.bench_function("Seriallize large Vec", |b| b.iter(|| {
-        let bytes = large_vec.to_cbor_bytes();
+        large_vec.to_cbor_bytes()
+    }));
+    let mut buffer = Vec::new();
+    group.bench_function("Seriallize large Vec into reused buffer", |b| b.iter(|| {
+        buffer.clear();
+        large_vec.encode_into(&mut buffer, &EncodeOptions::default());
+        buffer.len()
     }));
     let bytes = large_vec.to_cbor_bytes();
     group.bench_function("Deseriallize bytes to large Vec", |b| b.iter(|| {
-        let decoded_vec: Vec<i32> = decode_cbor(&bytes).unwrap();
+        decode_cbor::<Vec<i32>>(&bytes).unwrap()
     }));
 
 
 }
 
 criterion_group!(benches, my_benchmark);
-criterion_main!(benches);
\ No newline at end of file
+criterion_main!(benches);
@@ -0,0 +1,459 @@
+//! `#[derive(Cbor)]` for the `ezcbor::cbor::Cbor` trait.
+//!
+//! Structs with named fields are encoded as maps keyed by field name, tuple structs as arrays
+//! and unit structs as null. Use it through the `derive` feature of ezcbor.
+//!
+//! Enums pick their representation with `#[cbor(repr = "...")]`:
+//! - `"tag"` (the default): each variant is a tag around its payload. The tag number is the
+//!   variant index plus `tag_base`, which defaults to 6 like the hand-written `Item` sample.
+//! - `"map"`: externally tagged, `{"Variant": payload}`, with unit variants as a bare `"Variant"`.
+//! - `"index"`: the integer discriminant. Only for enums whose variants are all unit variants.
+//!
+//! Unit variants have null as their payload, newtype variants the payload itself,
+//! tuple variants an array and struct variants a map.
+//!
+//! `#[derive(Cbor)]` also implements `CborDecode`. Types with borrowed fields such as `&'a str`
+//! can't implement Cbor, so `#[derive(CborDecode)]` implements only `CborDecode`, with the same
+//! representation. Don't derive both on one type.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{ext::IdentExt, parse_macro_input, parse_quote, Attribute, Data, DataEnum, DeriveInput, Fields, GenericParam, Generics, Ident, LifetimeParam, LitInt, LitStr, Type};
+
+#[proc_macro_derive(Cbor, attributes(cbor))]
+pub fn derive_cbor(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    add_trait_bounds(&mut input.generics, parse_quote!(::ezcbor::cbor::Cbor));
+    let name = &input.ident;
+    let (encode, decode) = match derive_bodies(&input, Decoding::Owned) {
+        Ok(derived) => derived,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut decode_generics = input.generics.clone();
+    decode_generics.params.insert(0, parse_quote!('de));
+    let (decode_impl_generics, _, _) = decode_generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::ezcbor::cbor::Cbor for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn encode_into(&self, bytes: &mut ::std::vec::Vec<u8>, options: &::ezcbor::cbor::EncodeOptions) {
+                #encode
+            }
+
+            #[allow(unused_variables)]
+            fn from_cbor_bytes_with(bytes: &[u8], limits: &mut ::ezcbor::cbor::Limits) -> ::std::result::Result<(Self, usize), ::ezcbor::cbor::CborError>
+                where
+                    Self: Sized
+            {
+                #decode
+            }
+        }
+
+        impl #decode_impl_generics ::ezcbor::cbor::CborDecode<'de> for #name #ty_generics #where_clause {
+            fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut ::ezcbor::cbor::Limits) -> ::std::result::Result<(Self, usize), ::ezcbor::cbor::CborError> {
+                <Self as ::ezcbor::cbor::Cbor>::from_cbor_bytes_with(bytes, limits)
+            }
+        }
+    }.into()
+}
+
+#[proc_macro_derive(CborDecode, attributes(cbor))]
+pub fn derive_cbor_decode(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    add_trait_bounds(&mut input.generics, parse_quote!(::ezcbor::cbor::CborDecode<'de>));
+    let name = &input.ident;
+    let (_, decode) = match derive_bodies(&input, Decoding::Borrowed) {
+        Ok(derived) => derived,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    // 'de outlives every lifetime of the type, so borrowed fields can point into the input
+    let mut de: LifetimeParam = parse_quote!('de);
+    de.bounds.extend(input.generics.lifetimes().map(|param| param.lifetime.clone()));
+    let mut decode_generics = input.generics.clone();
+    decode_generics.params.insert(0, GenericParam::Lifetime(de));
+    let (impl_generics, _, _) = decode_generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::ezcbor::cbor::CborDecode<'de> for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut ::ezcbor::cbor::Limits) -> ::std::result::Result<(Self, usize), ::ezcbor::cbor::CborError> {
+                #decode
+            }
+        }
+    }.into()
+}
+
+/// Which trait decodes the fields.
+#[derive(Clone, Copy)]
+enum Decoding {
+    /// `Cbor::from_cbor_bytes_with`
+    Owned,
+    /// `CborDecode::from_cbor_slice_with`
+    Borrowed,
+}
+
+impl Decoding {
+    /// The function that decodes a `ty` from the bytes starting at it, within limits.
+    fn decode_fn(self, ty: &Type) -> TokenStream2 {
+        match self {
+            Decoding::Owned => quote!(<#ty as ::ezcbor::cbor::Cbor>::from_cbor_bytes_with),
+            Decoding::Borrowed => quote!(<#ty as ::ezcbor::cbor::CborDecode<'de>>::from_cbor_slice_with),
+        }
+    }
+}
+
+/// The encode and decode bodies for a struct or enum.
+fn derive_bodies(input: &DeriveInput, decoding: Decoding) -> syn::Result<(TokenStream2, TokenStream2)> {
+    let name = &input.ident;
+    match &input.data {
+        Data::Struct(data) => {
+            let accessors = field_accessors(&data.fields);
+            let encode = encode_fields(&data.fields, &accessors);
+            let decode = decode_fields(&data.fields, &quote!(Self), &name.to_string(), decoding);
+            Ok((encode, decode))
+        },
+        Data::Enum(data) => derive_enum(name, data, &input.attrs, decoding),
+        Data::Union(_) => Err(syn::Error::new_spanned(name, "Cbor can not be derived for unions")),
+    }
+}
+
+/// Every type parameter must itself implement the derived trait.
+fn add_trait_bounds(generics: &mut Generics, bound: syn::TypeParamBound) {
+    for param in &mut generics.params {
+        if let GenericParam::Type(type_param) = param {
+            type_param.bounds.push(bound.clone());
+        }
+    }
+}
+
+/// Expressions that borrow each field of `self`.
+fn field_accessors(fields: &Fields) -> Vec<TokenStream2> {
+    fields.iter().enumerate().map(|(index, field)| match &field.ident {
+        Some(ident) => quote!(&self.#ident),
+        None => {
+            let index = syn::Index::from(index);
+            quote!(&self.#index)
+        },
+    }).collect()
+}
+
+/// Name a field is stored under in the encoded map.
+fn field_key(ident: &Ident) -> String {
+    ident.unraw().to_string()
+}
+
+/// Statements that append the encoding of `fields` to `bytes`.
+/// `accessors` are expressions that borrow each field.
+fn encode_fields(fields: &Fields, accessors: &[TokenStream2]) -> TokenStream2 {
+    let len = fields.len() as u64;
+    match fields {
+        Fields::Named(named) => {
+            let keys: Vec<String> = named.named.iter().map(|field| field_key(field.ident.as_ref().unwrap())).collect();
+            // Deterministic encoding orders text keys by their encoding, which is by length and then bytewise
+            let mut sorted: Vec<usize> = (0..keys.len()).collect();
+            sorted.sort_by(|&a, &b| (keys[a].len(), &keys[a]).cmp(&(keys[b].len(), &keys[b])));
+            let sorted_keys = sorted.iter().map(|&i| &keys[i]);
+            let sorted_accessors = sorted.iter().map(|&i| &accessors[i]);
+            quote! {
+                ::ezcbor::cbor::write_head(bytes, 5, #len);
+                if options.deterministic {
+                    #(
+                        ::ezcbor::cbor::write_head(bytes, 3, #sorted_keys.len() as u64);
+                        bytes.extend_from_slice(#sorted_keys.as_bytes());
+                        ::ezcbor::cbor::Cbor::encode_into(#sorted_accessors, bytes, options);
+                    )*
+                } else {
+                    #(
+                        ::ezcbor::cbor::write_head(bytes, 3, #keys.len() as u64);
+                        bytes.extend_from_slice(#keys.as_bytes());
+                        ::ezcbor::cbor::Cbor::encode_into(#accessors, bytes, options);
+                    )*
+                }
+            }
+        },
+        Fields::Unnamed(_) => quote! {
+            ::ezcbor::cbor::write_head(bytes, 4, #len);
+            #(
+                ::ezcbor::cbor::Cbor::encode_into(#accessors, bytes, options);
+            )*
+        },
+        Fields::Unit => quote! {
+            bytes.push(0xf6);
+        },
+    }
+}
+
+/// An expression that decodes `fields` from `bytes` within `limits` and builds them with `constructor`,
+/// evaluating to `Result<(Self, usize), CborError>`. `type_name` is used in error messages.
+fn decode_fields(fields: &Fields, constructor: &TokenStream2, type_name: &str, decoding: Decoding) -> TokenStream2 {
+    let slots: Vec<Ident> = (0..fields.len()).map(|index| format_ident!("__field{}", index)).collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let decode_fns: Vec<_> = types.iter().map(|ty| decoding.decode_fn(ty)).collect();
+    let missing: Vec<String> = fields.iter().enumerate().map(|(index, field)| match &field.ident {
+        Some(ident) => format!("Missing field `{}` in {}", field_key(ident), type_name),
+        None => format!("Missing field {} in {}", index, type_name),
+    }).collect();
+    let values: Vec<TokenStream2> = slots.iter().zip(&missing).map(|(slot, missing)| quote! {
+        #slot.ok_or_else(|| ::ezcbor::cbor::CborError::from(
+            ::ezcbor::cbor::CborErrorKind::Unexpected(#missing.to_owned())
+        ))?
+    }).collect();
+    match fields {
+        Fields::Named(named) => {
+            let idents: Vec<_> = named.named.iter().map(|field| field.ident.as_ref().unwrap()).collect();
+            let keys = idents.iter().map(|ident| field_key(ident));
+            // A map with duplicate keys is not valid CBOR (RFC 8949 section 5.6), so don't let the last one win
+            let duplicates = idents.iter().map(|ident| format!("Duplicate field `{}` in {}", field_key(ident), type_name));
+            quote! {
+                #( let mut #slots: ::std::option::Option<#types> = ::std::option::Option::None; )*
+                let bytes_read = ::ezcbor::cbor::read_struct_fields(bytes, limits, |key, value_bytes, limits| {
+                    match key {
+                        #(
+                            #keys => {
+                                if #slots.is_some() {
+                                    return ::std::result::Result::Err(
+                                        ::ezcbor::cbor::CborErrorKind::Unexpected(#duplicates.to_owned()).into()
+                                    )
+                                }
+                                let (value, bytes_read) = #decode_fns(value_bytes, limits)?;
+                                #slots = ::std::option::Option::Some(value);
+                                ::std::result::Result::Ok(::std::option::Option::Some(bytes_read))
+                            },
+                        )*
+                        _ => ::std::result::Result::Ok(::std::option::Option::None),
+                    }
+                })?;
+                ::std::result::Result::Ok((#constructor { #( #idents: #values ),* }, bytes_read))
+            }
+        },
+        Fields::Unnamed(_) => {
+            let len = fields.len();
+            let indices = 0..len;
+            quote! {
+                #( let mut #slots: ::std::option::Option<#types> = ::std::option::Option::None; )*
+                let bytes_read = ::ezcbor::cbor::read_tuple_items(bytes, limits, #len, |index, item_bytes, limits| {
+                    match index {
+                        #(
+                            #indices => {
+                                let (item, bytes_read) = #decode_fns(item_bytes, limits)?;
+                                #slots = ::std::option::Option::Some(item);
+                                ::std::result::Result::Ok(bytes_read)
+                            },
+                        )*
+                        _ => ::std::result::Result::Ok(0),
+                    }
+                })?;
+                ::std::result::Result::Ok((#constructor( #(#values),* ), bytes_read))
+            }
+        },
+        Fields::Unit => quote! {
+            match ::ezcbor::cbor::byte_at(bytes, 0)? {
+                0xf6 => ::std::result::Result::Ok((#constructor, 1)),
+                found => ::std::result::Result::Err(
+                    ::ezcbor::cbor::CborErrorKind::TypeMismatch { expected: "null", found }.into()
+                ),
+            }
+        },
+    }
+}
+
+enum EnumRepr {
+    Tag(u64),
+    Map,
+    Index,
+}
+
+/// Reads the `#[cbor(repr = "...", tag_base = N)]` attributes of an enum.
+fn enum_repr(attrs: &[Attribute]) -> syn::Result<EnumRepr> {
+    let mut repr = String::from("tag");
+    let mut tag_base = 6;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("cbor")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("repr") {
+                repr = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("tag_base") {
+                tag_base = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                Ok(())
+            } else {
+                Err(meta.error("Expected `repr` or `tag_base`"))
+            }
+        })?;
+    }
+    match repr.as_str() {
+        "tag" => Ok(EnumRepr::Tag(tag_base)),
+        "map" => Ok(EnumRepr::Map),
+        "index" => Ok(EnumRepr::Index),
+        _ => Err(syn::Error::new_spanned(&attrs[0], "Expected repr to be \"tag\", \"map\" or \"index\"")),
+    }
+}
+
+/// Statements that append the payload of a variant to `bytes`, given the bindings of its fields.
+fn encode_payload(fields: &Fields, bindings: &[Ident]) -> TokenStream2 {
+    match fields {
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => {
+            let binding = &bindings[0];
+            quote! {
+                ::ezcbor::cbor::Cbor::encode_into(#binding, bytes, options);
+            }
+        },
+        _ => {
+            let accessors: Vec<TokenStream2> = bindings.iter().map(|binding| quote!(#binding)).collect();
+            encode_fields(fields, &accessors)
+        },
+    }
+}
+
+/// An expression that decodes the payload of a variant from `bytes` within `limits`,
+/// evaluating to `Result<(Self, usize), CborError>`.
+fn decode_payload(fields: &Fields, constructor: &TokenStream2, type_name: &str, decoding: Decoding) -> TokenStream2 {
+    match fields {
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => {
+            let decode_fn = decoding.decode_fn(&unnamed.unnamed[0].ty);
+            quote! {{
+                let (value, bytes_read) = #decode_fn(bytes, limits)?;
+                ::std::result::Result::Ok((#constructor(value), bytes_read))
+            }}
+        },
+        _ => {
+            let decode = decode_fields(fields, constructor, type_name, decoding);
+            quote!({ #decode })
+        },
+    }
+}
+
+/// The encode and decode bodies for an enum.
+fn derive_enum(name: &Ident, data: &DataEnum, attrs: &[Attribute], decoding: Decoding) -> syn::Result<(TokenStream2, TokenStream2)> {
+    if data.variants.is_empty() {
+        return Err(syn::Error::new_spanned(name, "Cbor can not be derived for enums without variants"))
+    }
+    let repr = enum_repr(attrs)?;
+    let enum_name = name.to_string();
+    let mut patterns = Vec::new();
+    let mut encodes = Vec::new();
+    let mut decodes = Vec::new();
+    for (index, variant) in data.variants.iter().enumerate() {
+        let ident = &variant.ident;
+        let variant_name = ident.unraw().to_string();
+        let constructor = quote!(Self::#ident);
+        let bindings: Vec<Ident> = (0..variant.fields.len()).map(|index| format_ident!("__field{}", index)).collect();
+        patterns.push(match &variant.fields {
+            Fields::Named(named) => {
+                let idents = named.named.iter().map(|field| &field.ident);
+                quote!(Self::#ident { #( #idents: #bindings ),* })
+            },
+            Fields::Unnamed(_) => quote!(Self::#ident( #(#bindings),* )),
+            Fields::Unit => quote!(Self::#ident),
+        });
+        let payload = encode_payload(&variant.fields, &bindings);
+        let decode = decode_payload(&variant.fields, &constructor, &format!("{}::{}", enum_name, variant_name), decoding);
+        match repr {
+            EnumRepr::Tag(tag_base) => {
+                let tag = tag_base + index as u64;
+                encodes.push(quote! {
+                    ::ezcbor::cbor::write_head(bytes, 6, #tag);
+                    #payload
+                });
+                decodes.push(quote!(#tag => #decode,));
+            },
+            EnumRepr::Map => match variant.fields {
+                Fields::Unit => {
+                    encodes.push(quote! {
+                        ::ezcbor::cbor::write_head(bytes, 3, #variant_name.len() as u64);
+                        bytes.extend_from_slice(#variant_name.as_bytes());
+                    });
+                    decodes.push(quote! {
+                        (#variant_name, ::std::option::Option::None) => ::std::result::Result::Ok((#constructor, 0)),
+                        (#variant_name, ::std::option::Option::Some(bytes)) => #decode,
+                    });
+                },
+                _ => {
+                    encodes.push(quote! {
+                        ::ezcbor::cbor::write_head(bytes, 5, 1);
+                        ::ezcbor::cbor::write_head(bytes, 3, #variant_name.len() as u64);
+                        bytes.extend_from_slice(#variant_name.as_bytes());
+                        #payload
+                    });
+                    let missing = format!("Variant {} of {} needs a payload", variant_name, enum_name);
+                    decodes.push(quote! {
+                        (#variant_name, ::std::option::Option::Some(bytes)) => #decode,
+                        (#variant_name, ::std::option::Option::None) => ::std::result::Result::Err(
+                            ::ezcbor::cbor::CborErrorKind::Unexpected(#missing.to_owned()).into()
+                        ),
+                    });
+                },
+            },
+            EnumRepr::Index => {
+                if !matches!(variant.fields, Fields::Unit) {
+                    return Err(syn::Error::new_spanned(variant, "repr = \"index\" only supports unit variants"))
+                }
+                encodes.push(quote!(#constructor as i128));
+                decodes.push(quote! {
+                    if discriminant == #constructor as i128 {
+                        return ::std::result::Result::Ok((#constructor, bytes_read))
+                    }
+                });
+            },
+        }
+    }
+    let unknown = |variant: TokenStream2| quote! {
+        ::std::result::Result::Err(::ezcbor::cbor::CborErrorKind::UnknownVariant(
+            ::std::format!("{} for {}", #variant, #enum_name)
+        ).into())
+    };
+    Ok(match repr {
+        EnumRepr::Tag(_) => {
+            let unknown = unknown(quote!(tag));
+            (
+                quote! {
+                    match self {
+                        #( #patterns => { #encodes }, )*
+                    }
+                },
+                quote! {
+                    ::ezcbor::cbor::read_enum_tag(bytes, limits, |tag, bytes, limits| match tag {
+                        #( #decodes )*
+                        _ => #unknown,
+                    })
+                },
+            )
+        },
+        EnumRepr::Map => {
+            let unknown = unknown(quote!(name));
+            (
+                quote! {
+                    match self {
+                        #( #patterns => { #encodes }, )*
+                    }
+                },
+                quote! {
+                    ::ezcbor::cbor::read_enum_map(bytes, limits, |name, payload, limits| match (name, payload) {
+                        #( #decodes )*
+                        (name, _) => #unknown,
+                    })
+                },
+            )
+        },
+        EnumRepr::Index => {
+            let unknown = unknown(quote!(discriminant));
+            (
+                quote! {
+                    let discriminant = match self {
+                        #( #patterns => #encodes, )*
+                    };
+                    if discriminant < 0 {
+                        ::ezcbor::cbor::write_head(bytes, 1, (-1 - discriminant) as u64);
+                    } else {
+                        ::ezcbor::cbor::write_head(bytes, 0, discriminant as u64);
+                    }
+                },
+                quote! {
+                    let (discriminant, bytes_read) = ::ezcbor::cbor::read_integer(bytes)?;
+                    #( #decodes )*
+                    #unknown
+                },
+            )
+        },
+    })
+}
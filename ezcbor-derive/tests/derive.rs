@@ -0,0 +1,298 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use ezcbor::cbor::{
+    decode_cbor, decode_cbor_borrowed, decode_cbor_with, is_deterministic, Cbor, CborErrorKind, DecodeOptions, EncodeOptions,
+    FloatEncoding, PathSegment, Value,
+};
+use ezcbor::{Cbor, CborDecode};
+
+#[derive(Cbor, Debug, PartialEq)]
+struct Reading {
+    sensor: String,
+    values: Vec<i32>,
+    r#type: u8,
+}
+
+#[derive(Cbor, Debug, PartialEq)]
+struct Pair(u16, String);
+
+#[derive(Cbor, Debug, PartialEq)]
+struct Marker;
+
+#[derive(Cbor, Debug, PartialEq)]
+struct Wrapper<T> {
+    inner: T,
+    tags: HashMap<String, Pair>,
+}
+
+#[test]
+fn named_struct_is_a_map() {
+    let reading = Reading { sensor: "temp".to_owned(), values: vec![-1, 1000], r#type: 3 };
+    let bytes = reading.to_cbor_bytes();
+    let value: Value = decode_cbor(&bytes).unwrap();
+    assert_eq!(value.get("sensor"), Some(&Value::Text("temp".to_owned())));
+    assert_eq!(value.get("type"), Some(&Value::Unsigned(3)));
+    assert_eq!(decode_cbor::<Reading>(&bytes).unwrap(), reading);
+}
+
+#[test]
+fn named_struct_ignores_order_and_unknown_fields() {
+    let value = Value::Map(vec![
+        (Value::Text("type".to_owned()), Value::Unsigned(1)),
+        (Value::Text("extra".to_owned()), Value::Array(vec![Value::Null, Value::Tag(1, Box::new(Value::Unsigned(0)))])),
+        (Value::Text("values".to_owned()), Value::Array(vec![])),
+        (Value::Text("sensor".to_owned()), Value::Text("x".to_owned())),
+    ]);
+    let decoded: Reading = decode_cbor(&value.to_cbor_bytes()).unwrap();
+    assert_eq!(decoded, Reading { sensor: "x".to_owned(), values: vec![], r#type: 1 });
+}
+
+#[test]
+fn named_struct_errors() {
+    let value = Value::Map(vec![(Value::Text("sensor".to_owned()), Value::Text("x".to_owned()))]);
+    let error = decode_cbor::<Reading>(&value.to_cbor_bytes()).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::Unexpected("Missing field `values` in Reading".to_owned()));
+
+    let value = Value::Map(vec![
+        (Value::Text("sensor".to_owned()), Value::Text("x".to_owned())),
+        (Value::Text("values".to_owned()), Value::Array(vec![Value::Unsigned(1), Value::Text("2".to_owned())])),
+        (Value::Text("type".to_owned()), Value::Unsigned(1)),
+    ]);
+    let error = decode_cbor::<Reading>(&value.to_cbor_bytes()).unwrap_err();
+    assert_eq!(error.path, vec![PathSegment::Key("values".to_owned()), PathSegment::Index(1)]);
+
+    let value = Value::Map(vec![
+        (Value::Text("sensor".to_owned()), Value::Text("x".to_owned())),
+        (Value::Text("values".to_owned()), Value::Array(vec![])),
+        (Value::Text("sensor".to_owned()), Value::Text("y".to_owned())),
+    ]);
+    let error = decode_cbor::<Reading>(&value.to_cbor_bytes()).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::Unexpected("Duplicate field `sensor` in Reading".to_owned()));
+    assert_eq!(error.path, vec![PathSegment::Key("sensor".to_owned())]);
+}
+
+#[test]
+fn tuple_and_unit_structs() {
+    let pair = Pair(7, "seven".to_owned());
+    let bytes = pair.to_cbor_bytes();
+    assert_eq!(bytes[..2], [0x82, 0x07]);
+    assert_eq!(decode_cbor::<Pair>(&bytes).unwrap(), pair);
+    assert_eq!(decode_cbor::<Pair>(&[0x9f, 0x07, 0x60, 0xff]).unwrap(), Pair(7, String::new()));
+    assert!(decode_cbor::<Pair>(&[0x81, 0x07]).is_err());
+    assert!(decode_cbor::<Pair>(&[0x9f, 0x07, 0x60, 0x01, 0xff]).is_err());
+
+    assert_eq!(Marker.to_cbor_bytes(), vec![0xf6]);
+    assert_eq!(decode_cbor::<Marker>(&[0xf6]).unwrap(), Marker);
+    assert!(decode_cbor::<Marker>(&[0xf7]).is_err());
+}
+
+#[test]
+fn generic_struct() {
+    let mut tags = HashMap::new();
+    tags.insert("a".to_owned(), Pair(1, "one".to_owned()));
+    let wrapper = Wrapper { inner: vec![Marker, Marker], tags };
+    let bytes = wrapper.to_cbor_bytes();
+    assert_eq!(decode_cbor::<Wrapper<Vec<Marker>>>(&bytes).unwrap(), wrapper);
+}
+
+#[derive(Cbor, Debug, PartialEq)]
+enum Item {
+    Int(Vec<i32>),
+    Float(Vec<f32>),
+    String(Vec<String>),
+}
+
+#[derive(Cbor, Debug, PartialEq)]
+#[cbor(repr = "tag", tag_base = 1000)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Rect(u32, u32),
+    Polygon { bytes: Vec<u8>, closed: bool },
+}
+
+#[derive(Cbor, Debug, PartialEq)]
+#[cbor(repr = "map")]
+enum Command<T> {
+    Stop,
+    Move(T),
+    Goto(i64, i64),
+    Say { text: String },
+}
+
+#[derive(Cbor, Debug, PartialEq)]
+#[cbor(repr = "index")]
+enum Level {
+    Low = -1,
+    Medium,
+    High = 10,
+}
+
+#[derive(Cbor, Debug, PartialEq)]
+struct Point(f64, Vec<f32>);
+
+#[test]
+fn encode_options_reach_fields() {
+    let point = Point(1.5, vec![0.25]);
+    let options = EncodeOptions { float_encoding: FloatEncoding::Shortest, ..Default::default() };
+    let bytes = point.to_cbor_bytes_with(&options);
+    assert_eq!(bytes, vec![0x82, 0xf9, 0x3e, 0x00, 0x81, 0xf9, 0x34, 0x00]);
+    assert_eq!(decode_cbor::<Point>(&bytes).unwrap(), point);
+    assert_eq!(point.to_cbor_bytes()[1], 0xfb);
+}
+
+#[test]
+fn deterministic_struct_keys() {
+    let reading = Reading { sensor: "temp".to_owned(), values: vec![1], r#type: 3 };
+    let bytes = reading.to_cbor_bytes_with(&EncodeOptions::deterministic());
+    assert!(is_deterministic(&bytes));
+    assert!(!is_deterministic(&reading.to_cbor_bytes()));
+    assert_eq!(&bytes[1..6], &[0x64, b't', b'y', b'p', b'e']);
+    assert_eq!(decode_cbor::<Reading>(&bytes).unwrap(), reading);
+}
+
+#[derive(Cbor, Debug, PartialEq)]
+struct Tree {
+    children: Vec<Tree>,
+}
+
+#[test]
+fn decode_limits_reach_fields() {
+    let wrapper = Wrapper { inner: vec![Pair(1, "a".to_owned())], tags: HashMap::from([("k".to_owned(), Pair(2, "bcdefgh".to_owned()))]) };
+    let bytes = wrapper.to_cbor_bytes();
+    let options = DecodeOptions { max_depth: 2, ..Default::default() };
+    let error = decode_cbor_with::<Wrapper<Vec<Pair>>>(&bytes, &options).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::DepthExceeded(2));
+    assert_eq!(error.path, vec![PathSegment::Key("inner".to_owned()), PathSegment::Index(0)]);
+    let options = DecodeOptions { max_depth: 3, ..Default::default() };
+    assert_eq!(decode_cbor_with::<Wrapper<Vec<Pair>>>(&bytes, &options).unwrap(), wrapper);
+
+    let options = DecodeOptions { max_string_len: 6, ..Default::default() };
+    let error = decode_cbor_with::<Wrapper<Vec<Pair>>>(&bytes, &options).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::LengthTooLarge(7));
+    assert_eq!(error.path, vec![PathSegment::Key("tags".to_owned()), PathSegment::Key("k".to_owned()), PathSegment::Index(1)]);
+
+    // Recursive types stop at the depth limit
+    let mut tree = Tree { children: vec![] };
+    for _ in 0..100 {
+        tree = Tree { children: vec![tree] };
+    }
+    let bytes = tree.to_cbor_bytes();
+    assert_eq!(decode_cbor::<Tree>(&bytes).unwrap_err().kind, CborErrorKind::DepthExceeded(128));
+    let options = DecodeOptions { max_depth: 202, ..Default::default() };
+    assert_eq!(decode_cbor_with::<Tree>(&bytes, &options).unwrap(), tree);
+}
+
+#[test]
+fn tag_enum_matches_hand_written_item() {
+    let item = Item::String(vec!["a".to_owned()]);
+    let bytes = item.to_cbor_bytes();
+    assert_eq!(bytes, ezcbor::cbor::Item::String(vec!["a".to_owned()]).to_cbor_bytes());
+    assert_eq!(decode_cbor::<Item>(&bytes).unwrap(), item);
+    assert_eq!(decode_cbor::<ezcbor::cbor::Item>(&Item::Int(vec![-5]).to_cbor_bytes()).unwrap(), ezcbor::cbor::Item::Int(vec![-5]));
+}
+
+#[test]
+fn tag_enum() {
+    for shape in [
+        Shape::Empty,
+        Shape::Circle(1.5),
+        Shape::Rect(3, 4),
+        Shape::Polygon { bytes: vec![1, 2], closed: true },
+    ] {
+        let bytes = shape.to_cbor_bytes();
+        assert_eq!(decode_cbor::<Shape>(&bytes).unwrap(), shape);
+    }
+    assert_eq!(Shape::Rect(3, 4).to_cbor_bytes(), vec![0xd9, 0x03, 0xea, 0x82, 0x03, 0x04]);
+    assert_eq!(Shape::Empty.to_cbor_bytes(), vec![0xd9, 0x03, 0xe8, 0xf6]);
+
+    let error = decode_cbor::<Shape>(&[0xd9, 0x03, 0xec, 0xf6]).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::UnknownVariant("1004 for Shape".to_owned()));
+    assert_eq!(error.to_string(), "unknown variant 1004 for Shape at byte 3");
+}
+
+#[test]
+fn map_enum() {
+    for command in [
+        Command::Stop,
+        Command::Move(-3i8),
+        Command::Goto(1, -1),
+        Command::Say { text: "hi".to_owned() },
+    ] {
+        let bytes = command.to_cbor_bytes();
+        assert_eq!(decode_cbor::<Command<i8>>(&bytes).unwrap(), command);
+    }
+    assert_eq!(Command::<u8>::Stop.to_cbor_bytes(), vec![0x64, b'S', b't', b'o', b'p']);
+    let value: Value = decode_cbor(&Command::Move(5u8).to_cbor_bytes()).unwrap();
+    assert_eq!(value.get("Move"), Some(&Value::Unsigned(5)));
+
+    let error = decode_cbor::<Command<u8>>(&[0x63, b'R', b'u', b'n']).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::UnknownVariant("Run for Command".to_owned()));
+    assert!(decode_cbor::<Command<u8>>(&[0xa2, 0x64, b'M', b'o', b'v', b'e', 0x01, 0x64, b'M', b'o', b'v', b'e', 0x01]).is_err());
+    let error = decode_cbor::<Command<u8>>(&[0x64, b'M', b'o', b'v', b'e']).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::Unexpected("Variant Move of Command needs a payload".to_owned()));
+}
+
+#[test]
+fn index_enum() {
+    assert_eq!(Level::Low.to_cbor_bytes(), vec![0x20]);
+    assert_eq!(Level::Medium.to_cbor_bytes(), vec![0x00]);
+    assert_eq!(Level::High.to_cbor_bytes(), vec![0x0a]);
+    assert_eq!(decode_cbor::<Level>(&[0x0a]).unwrap(), Level::High);
+    assert_eq!(decode_cbor::<Level>(&[0x20]).unwrap(), Level::Low);
+    let error = decode_cbor::<Level>(&[0x01]).unwrap_err();
+    assert_eq!(error.kind, CborErrorKind::UnknownVariant("1 for Level".to_owned()));
+}
+
+#[derive(CborDecode, Debug, PartialEq)]
+struct Record<'a, T> {
+    name: &'a str,
+    payload: &'a [u8],
+    notes: Vec<Cow<'a, str>>,
+    readings: Vec<Reading>,
+    extra: T,
+}
+
+#[derive(CborDecode, Debug, PartialEq)]
+#[cbor(repr = "map")]
+enum Event<'a> {
+    Message(&'a str),
+    Rename { from: &'a str, to: &'a str },
+    Clear,
+}
+
+#[test]
+fn borrowed_struct() {
+    let value = Value::Map(vec![
+        (Value::Text("extra".to_owned()), Value::Unsigned(7)),
+        (Value::Text("name".to_owned()), Value::Text("log".to_owned())),
+        (Value::Text("payload".to_owned()), Value::Bytes(vec![1, 2])),
+        (Value::Text("notes".to_owned()), Value::Array(vec![Value::Text("a".to_owned())])),
+        (Value::Text("readings".to_owned()), Value::Array(vec![])),
+    ]);
+    let bytes = value.to_cbor_bytes();
+    let record: Record<u8> = decode_cbor_borrowed(&bytes).unwrap();
+    assert_eq!(record, Record { name: "log", payload: &[1, 2], notes: vec![Cow::Borrowed("a")], readings: vec![], extra: 7 });
+    assert!(bytes.as_ptr_range().contains(&record.name.as_ptr()));
+
+    let error = decode_cbor_borrowed::<Record<u8>>(&bytes[..bytes.len() - 1]).unwrap_err();
+    assert_eq!(error.path, vec![PathSegment::Key("readings".to_owned())]);
+}
+
+#[test]
+fn borrowed_enum() {
+    let rename = Value::Map(vec![(
+        Value::Text("Rename".to_owned()),
+        Value::Map(vec![
+            (Value::Text("from".to_owned()), Value::Text("a".to_owned())),
+            (Value::Text("to".to_owned()), Value::Text("b".to_owned())),
+        ]),
+    )]);
+    let messages = Value::Array(vec![
+        Value::Map(vec![(Value::Text("Message".to_owned()), Value::Text("hi".to_owned()))]),
+        rename,
+        Value::Text("Clear".to_owned()),
+    ]).to_cbor_bytes();
+    let events: Vec<Event> = decode_cbor_borrowed(&messages).unwrap();
+    assert_eq!(events, vec![Event::Message("hi"), Event::Rename { from: "a", to: "b" }, Event::Clear]);
+}
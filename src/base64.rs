@@ -0,0 +1,78 @@
+//! Base64 (RFC 4648) for byte strings in diagnostic notation and JSON. Public only so that the
+//! command-line tool can share it; it is not part of the supported API.
+
+/// Encodes bytes in base64, either in the standard alphabet with padding (RFC 4648 section 4)
+/// or in the URL-safe alphabet without padding (section 5).
+pub fn encode_base64(bytes: &[u8], url_safe: bool) -> String {
+    let alphabet: &[u8; 64] = match url_safe {
+        true => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_",
+        false => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/",
+    };
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let buffer = chunk.iter().enumerate().fold(0u32, |buffer, (i, byte)| buffer | (*byte as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(alphabet[(buffer >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+        if !url_safe {
+            for _ in chunk.len()..3 {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes base64 in either the standard or the URL-safe alphabet. Padding and whitespace are ignored.
+pub fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => continue,
+            _ => return None,
+        };
+        buffer = buffer << 6 | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    // A single character left over is not a whole byte
+    match bits {
+        6 => None,
+        _ => Some(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        let cases: [(&[u8], &str, &str); 5] = [
+            (b"", "", ""),
+            (b"f", "Zg==", "Zg"),
+            (b"fo", "Zm8=", "Zm8"),
+            (b"foo", "Zm9v", "Zm9v"),
+            (&[0xfb, 0xff, 0xbf], "+/+/", "-_-_"),
+        ];
+        for (bytes, standard, url_safe) in cases {
+            assert_eq!(encode_base64(bytes, false), standard);
+            assert_eq!(encode_base64(bytes, true), url_safe);
+            assert_eq!(decode_base64(standard).unwrap(), bytes);
+            assert_eq!(decode_base64(url_safe).unwrap(), bytes);
+        }
+        assert_eq!(decode_base64("Zm9v\n YmFy").unwrap(), b"foobar");
+        assert_eq!(decode_base64("Z"), None);
+        assert_eq!(decode_base64("Zm9v!"), None);
+    }
+}
@@ -1,4 +1,10 @@
-use std::{any::type_name, collections::{BTreeMap, BTreeSet, HashMap, HashSet}, hash::Hash};
+//! Encoding and decoding of CBOR data items (RFC 8949).
+//!
+//! `validate`, `skip_item` and `check_deterministic` walk nested items without recursing: open
+//! arrays, maps and tags are kept on a heap stack, so how deeply input may nest is bounded only
+//! by memory.
+
+use std::{borrow::Cow, collections::{BTreeMap, BTreeSet, HashMap, HashSet}, hash::Hash, ops::Range};
 
 
 pub enum DataItem {
@@ -37,6 +43,10 @@ pub enum DataItem {
     Map8,
     TerminatedMap,
     Tag(u8),
+    Tag1,
+    Tag2,
+    Tag4,
+    Tag8,
     SimpleOrFloat,
     NotSupported,
     UnsignedBigNum,
@@ -51,24 +61,380 @@ pub enum DataItem {
     InvalidByte,
 }
 
+/// Decodes the data item at the start of `bytes`. Anything after it is ignored;
+/// use `decode_cbor_exact` to reject it.
 #[inline]
 pub fn decode_cbor<T>(bytes: &[u8]) -> Result<T, CborError> where T: Cbor {
     let (t, _) = <T as Cbor>::from_cbor_bytes(bytes)?;
     Ok(t)
 }
 
-#[derive(Debug)]
-pub enum CborError {
+/// Like `decode_cbor`, within the given options.
+#[inline]
+pub fn decode_cbor_with<T>(bytes: &[u8], options: &DecodeOptions) -> Result<T, CborError> where T: Cbor {
+    let (t, _) = <T as Cbor>::from_cbor_bytes_with(bytes, &mut Limits::new(*options))?;
+    Ok(t)
+}
+
+/// Decodes `bytes` as exactly one data item, with `TrailingBytes` if anything follows it.
+#[inline]
+pub fn decode_cbor_exact<T>(bytes: &[u8]) -> Result<T, CborError> where T: Cbor {
+    let (t, rest) = decode_cbor_prefix(bytes)?;
+    if !rest.is_empty() {
+        return Err(CborError::new(CborErrorKind::TrailingBytes(rest.len())).at(bytes.len() - rest.len()))
+    }
+    Ok(t)
+}
+
+/// Decodes the data item at the start of `bytes` and returns it with the bytes after it.
+#[inline]
+pub fn decode_cbor_prefix<T>(bytes: &[u8]) -> Result<(T, &[u8]), CborError> where T: Cbor {
+    let (t, bytes_read) = <T as Cbor>::from_cbor_bytes(bytes)?;
+    Ok((t, bytes_from(bytes, bytes_read)?))
+}
+
+/// An error from decoding, with the byte offset it occurred at and the logical path to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CborError {
+    pub kind: CborErrorKind,
+    /// Offset of the offending byte from the start of the input.
+    pub offset: usize,
+    /// Where in the decoded value the error occurred, outermost first.
+    pub path: Vec<PathSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CborErrorKind {
+    /// The input ended early. `needed` is the number of additional bytes needed to continue.
+    UnexpectedEnd { needed: usize },
+    /// The data item is not of the expected type. `found` is its initial byte.
+    TypeMismatch { expected: &'static str, found: u8 },
+    /// The integer does not fit in the target type.
+    IntegerOverflow { value: i128, target: &'static str },
+    /// A text string is not valid utf-8.
+    InvalidUtf8,
+    /// A declared length is too large to be decoded.
+    LengthTooLarge(u64),
+    /// Data items are nested deeper than the given limit.
+    DepthExceeded(usize),
+    /// Decoding would allocate more than the given number of bytes.
+    AllocationExceeded(usize),
+    /// The given number of bytes are left over after the data item.
+    TrailingBytes(usize),
+    /// An enum variant tag, name or index that the enum does not have.
+    UnknownVariant(String),
+    /// The input is not well-formed CBOR.
     IllFormed(String),
+    /// The input is well-formed, but not what the implementation expected.
     Unexpected(String),
+    /// The input is well-formed, but not deterministically encoded.
+    NotDeterministic(String),
+    /// The text is not valid diagnostic notation. The offset is in bytes of text.
+    InvalidDiagnostic(String),
+    /// The text is not valid JSON. The offset is in bytes of text.
+    InvalidJson(String),
+    /// Reading the input failed.
+    Io { kind: std::io::ErrorKind, message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// Position in an array, or of a map entry whose key could not be decoded.
+    Index(usize),
+    /// Key of a map entry, rendered as text.
+    Key(String),
+}
+
+impl CborError {
+    pub fn new(kind: CborErrorKind) -> Self {
+        CborError { kind, offset: 0, path: Vec::new() }
+    }
+
+    /// Moves the error forward by `offset` bytes. Used when an error from a nested item is passed up.
+    pub fn at(mut self, offset: usize) -> Self {
+        self.offset += offset;
+        self
+    }
+
+    /// Records that the error occurred inside the item at `index`.
+    pub fn in_index(mut self, index: usize) -> Self {
+        self.path.insert(0, PathSegment::Index(index));
+        self
+    }
+
+    /// Records that the error occurred inside the value of the map entry with key `key`.
+    pub fn in_key(mut self, key: String) -> Self {
+        self.path.insert(0, PathSegment::Key(key));
+        self
+    }
+}
+
+impl From<std::io::Error> for CborError {
+    fn from(error: std::io::Error) -> Self {
+        CborError::new(CborErrorKind::Io { kind: error.kind(), message: error.to_string() })
+    }
+}
+
+impl From<CborErrorKind> for CborError {
+    fn from(kind: CborErrorKind) -> Self {
+        CborError::new(kind)
+    }
 }
 
+impl std::fmt::Display for CborError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.kind, self.offset)?;
+        if !self.path.is_empty() {
+            write!(f, " in ")?;
+            for segment in &self.path {
+                write!(f, "{}", segment)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for CborErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CborErrorKind::UnexpectedEnd { needed } => write!(f, "unexpected end of input, {} more byte(s) needed", needed),
+            CborErrorKind::TypeMismatch { expected, found } => write!(f, "expected {}, found {} (0x{:02x})", expected, major_type_name(*found), found),
+            CborErrorKind::IntegerOverflow { value, target } => write!(f, "{} does not fit in {}", value, target),
+            CborErrorKind::InvalidUtf8 => write!(f, "text string is not valid utf-8"),
+            CborErrorKind::LengthTooLarge(len) => write!(f, "length {} is too large", len),
+            CborErrorKind::DepthExceeded(limit) => write!(f, "nesting depth exceeds the limit of {}", limit),
+            CborErrorKind::AllocationExceeded(limit) => write!(f, "decoding would allocate more than the limit of {} bytes", limit),
+            CborErrorKind::TrailingBytes(count) => write!(f, "{} trailing byte(s) after the data item", count),
+            CborErrorKind::UnknownVariant(variant) => write!(f, "unknown variant {}", variant),
+            CborErrorKind::IllFormed(message) => write!(f, "{}", message),
+            CborErrorKind::Unexpected(message) => write!(f, "{}", message),
+            CborErrorKind::NotDeterministic(message) => write!(f, "not deterministic: {}", message),
+            CborErrorKind::InvalidDiagnostic(message) => write!(f, "invalid diagnostic notation: {}", message),
+            CborErrorKind::InvalidJson(message) => write!(f, "invalid json: {}", message),
+            CborErrorKind::Io { message, .. } => write!(f, "i/o error: {}", message),
+        }
+    }
+}
+
+impl std::fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathSegment::Index(index) => write!(f, "[{}]", index),
+            PathSegment::Key(key) => write!(f, ".{}", key),
+        }
+    }
+}
+
+impl std::error::Error for CborError {}
+
+/// Name of the major type of a data item with the given initial byte.
+pub fn major_type_name(initial_byte: u8) -> &'static str {
+    match initial_byte >> 5 {
+        0 => "unsigned integer",
+        1 => "negative integer",
+        2 => "byte string",
+        3 => "text string",
+        4 => "array",
+        5 => "map",
+        6 => "tag",
+        _ => "simple value or float",
+    }
+}
+
+/// Renders an encoded map key for use in an error path.
+/// Text and integer keys are shown as their value, anything else as hex.
+pub(crate) fn describe_key(key_bytes: &[u8]) -> String {
+    if let Ok((text, _)) = <String as Cbor>::from_cbor_bytes(key_bytes) {
+        return text
+    }
+    if let Ok((num, _)) = read_integer(key_bytes) {
+        return num.to_string()
+    }
+    key_bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Implementations override at least one of `to_cbor_bytes` and `encode_into`, and at least one
+/// of `from_cbor_bytes` and `from_cbor_bytes_with`. The defaults in each pair are written in
+/// terms of each other, so overriding neither recurses forever. Prefer `encode_into` and
+/// `from_cbor_bytes_with`: the other defaults drop the options and limits.
 pub trait Cbor {
-    fn to_cbor_bytes(&self) -> Vec<u8>;
+    fn to_cbor_bytes(&self) -> Vec<u8> {
+        self.to_cbor_bytes_with(&EncodeOptions::default())
+    }
+
+    /// Encodes with the given options.
+    fn to_cbor_bytes_with(&self, options: &EncodeOptions) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.encode_into(&mut bytes, options);
+        bytes
+    }
+
+    /// Appends the encoding to `bytes`. Implementations that hold other Cbor values should
+    /// encode them with `encode_into` too, passing the options on. The default appends
+    /// `to_cbor_bytes`, ignoring the options.
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
+        bytes.extend_from_slice(&self.to_cbor_bytes());
+    }
 
     fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
         where 
-            Self: Sized;
+            Self: Sized
+    {
+        Self::from_cbor_bytes_with(bytes, &mut Limits::default())
+    }
+
+    /// Decodes within `limits`. Implementations that hold other Cbor values should decode them
+    /// with `from_cbor_bytes_with` too, passing the limits on. The default calls
+    /// `from_cbor_bytes`, ignoring the limits.
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized
+    {
+        Self::from_cbor_bytes(bytes)
+    }
+}
+
+/// Options for `Cbor::to_cbor_bytes_with`. The default matches `Cbor::to_cbor_bytes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub float_encoding: FloatEncoding,
+    /// Sort map entries by their encoded key and set items by their encoding, so that equal
+    /// maps and sets always produce the same bytes.
+    pub deterministic: bool,
+}
+
+impl EncodeOptions {
+    /// Core deterministic encoding (RFC 8949 section 4.2.1): shortest heads and floats,
+    /// definite lengths and map keys sorted bytewise by their encoding.
+    pub fn deterministic() -> Self {
+        EncodeOptions { float_encoding: FloatEncoding::Shortest, deterministic: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FloatEncoding {
+    /// f32 as single precision, f64 as double precision and `Value::Float` in its `FloatWidth`.
+    #[default]
+    Native,
+    /// The shortest of half, single or double precision that holds the value exactly
+    /// (RFC 8949 section 4.2.2). NaN is written as the half precision quiet NaN 0xf97e00.
+    Shortest,
+}
+
+/// The precision a `Value::Float` is written in under `FloatEncoding::Native`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatWidth {
+    Half,
+    Single,
+    Double,
+}
+
+/// Bounds on the resources a decode may use, for input that can't be trusted. The default only
+/// limits nesting depth; lengths are then only bounded by the size of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// How deeply arrays, maps and tags may nest.
+    pub max_depth: usize,
+    /// Most items in an array or entries in a map.
+    pub max_collection_len: usize,
+    /// Longest byte or text string, in bytes.
+    pub max_string_len: usize,
+    /// Most bytes allocated over the whole decode, counting strings by their length and
+    /// collections by the size of their items.
+    pub max_allocation: usize,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            max_depth: 128,
+            max_collection_len: usize::MAX,
+            max_string_len: usize::MAX,
+            max_allocation: usize::MAX,
+        }
+    }
+}
+
+/// The `DecodeOptions` of a decode and what it has used of them so far.
+/// Passed to `Cbor::from_cbor_bytes_with` and `CborDecode::from_cbor_slice_with`.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    options: DecodeOptions,
+    depth: usize,
+    allocated: usize,
+}
+
+impl Limits {
+    pub fn new(options: DecodeOptions) -> Self {
+        Limits { options, depth: 0, allocated: 0 }
+    }
+
+    pub fn options(&self) -> &DecodeOptions {
+        &self.options
+    }
+
+    /// Bytes counted against `max_allocation` so far.
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Runs `decode` one level deeper, with `DepthExceeded` if that is past `max_depth`.
+    pub fn nested<T>(&mut self, decode: impl FnOnce(&mut Self) -> Result<T, CborError>) -> Result<T, CborError> {
+        self.enter()?;
+        let result = decode(self);
+        self.leave();
+        result
+    }
+
+    /// Goes one level deeper. Every successful `enter` must be matched by a `leave`.
+    pub(crate) fn enter(&mut self) -> Result<(), CborError> {
+        if self.depth >= self.options.max_depth {
+            return Err(CborErrorKind::DepthExceeded(self.options.max_depth).into())
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Checks a collection length against `max_collection_len`.
+    pub fn check_collection_len(&self, len: usize) -> Result<(), CborError> {
+        if len > self.options.max_collection_len {
+            return Err(CborErrorKind::LengthTooLarge(len as u64).into())
+        }
+        Ok(())
+    }
+
+    /// Checks a string length against `max_string_len`.
+    pub fn check_string_len(&self, len: usize) -> Result<(), CborError> {
+        if len > self.options.max_string_len {
+            return Err(CborErrorKind::LengthTooLarge(len as u64).into())
+        }
+        Ok(())
+    }
+
+    /// Counts `size` bytes against `max_allocation`.
+    pub fn allocate(&mut self, size: usize) -> Result<(), CborError> {
+        match self.allocated.checked_add(size) {
+            Some(allocated) if allocated <= self.options.max_allocation => {
+                self.allocated = allocated;
+                Ok(())
+            },
+            _ => Err(CborErrorKind::AllocationExceeded(self.options.max_allocation).into()),
+        }
+    }
+
+    /// Checks a string against `max_string_len` and counts it against `max_allocation` if it was copied.
+    pub fn check_string<'a, T>(&mut self, string: Cow<'a, T>) -> Result<Cow<'a, T>, CborError> where T: ToOwned + AsRef<[u8]> + ?Sized {
+        let len = AsRef::<[u8]>::as_ref(&*string).len();
+        self.check_string_len(len)?;
+        if let Cow::Owned(_) = string {
+            self.allocate(len)?;
+        }
+        Ok(string)
+    }
 }
 
 pub trait ToCbor {
@@ -78,14 +444,9 @@ pub trait ToCbor {
 impl<T> ToCbor for &[T] where T: Cbor +  {
     fn to_cbor_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-            if self.len() < 24 {
-                bytes.push(0x80 + self.len() as u8);
-            } else {
-                bytes.push(0x9b);
-                bytes.extend_from_slice(&self.len().to_be_bytes());
-            }
-            for i in 0..self.len() {
-                bytes.extend_from_slice(&self[i].to_cbor_bytes());
+            write_head(&mut bytes, 4, self.len() as u64);
+            for item in self.iter() {
+                item.encode_into(&mut bytes, &EncodeOptions::default());
             }
         bytes
     }
@@ -93,738 +454,1613 @@ impl<T> ToCbor for &[T] where T: Cbor +  {
 
 pub fn byteslice_to_cbor(byteslice: &[u8]) -> Vec<u8> {
     let mut bytes = Vec::new();
-        if byteslice.len() < 24 {
-            bytes.push(0x40 + byteslice.len() as u8);
-            bytes.extend_from_slice(byteslice);
-            bytes
-        } else {
-            bytes.push(0x5b);
-            bytes.extend_from_slice(&byteslice.len().to_be_bytes());
-            bytes.extend_from_slice(byteslice);
-            bytes
-        }
+        write_head(&mut bytes, 2, byteslice.len() as u64);
+        bytes.extend_from_slice(byteslice);
+        bytes
 }
 
 pub fn byteslice_from_cbor(bytes: &[u8]) -> Result<(Vec<u8>, usize), CborError> {
-    let mut v = Vec::new();
-        let bytes_read;
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallByteString(byte) => {
-                v.extend_from_slice(&bytes[1..1+byte as usize]);
-                bytes_read = byte+1;
+    let (content, bytes_read) = read_byte_string(bytes)?;
+    Ok((content.into_owned(), bytes_read))
+}
+
+/// Returns the byte at `index`, or an error saying how many more bytes are needed to reach it.
+#[inline]
+pub fn byte_at(bytes: &[u8], index: usize) -> Result<u8, CborError> {
+    match bytes.get(index) {
+        Some(byte) => Ok(*byte),
+        None => Err(CborError::from(CborErrorKind::UnexpectedEnd { needed: index.saturating_add(1) - bytes.len() }).at(bytes.len())),
+    }
+}
+
+/// Returns the `len` bytes starting at `start`, or an error saying how many more bytes are needed.
+/// Lengths that would overflow the address space are reported as ill-formed.
+#[inline]
+pub fn bytes_at(bytes: &[u8], start: usize, len: usize) -> Result<&[u8], CborError> {
+    let end = match start.checked_add(len) {
+        Some(end) => end,
+        None => return Err(CborError::from(CborErrorKind::LengthTooLarge(len as u64)).at(start)),
+    };
+    match bytes.get(start..end) {
+        Some(slice) => Ok(slice),
+        None => Err(CborError::from(CborErrorKind::UnexpectedEnd { needed: end - bytes.len() }).at(bytes.len())),
+    }
+}
+
+/// Returns everything from `start` onwards, or an error if `start` is past the end of the input.
+#[inline]
+pub fn bytes_from(bytes: &[u8], start: usize) -> Result<&[u8], CborError> {
+    match bytes.get(start..) {
+        Some(slice) => Ok(slice),
+        None => Err(CborError::from(CborErrorKind::UnexpectedEnd { needed: start - bytes.len() }).at(bytes.len())),
+    }
+}
+
+/// Copies the `N` bytes starting at `start` into an array, e.g. for `u32::from_be_bytes`.
+#[inline]
+pub fn array_at<const N: usize>(bytes: &[u8], start: usize) -> Result<[u8; N], CborError> {
+    let mut array = [0; N];
+    array.copy_from_slice(bytes_at(bytes, start, N)?);
+    Ok(array)
+}
+
+/// Writes the head of a data item with the given major type (0..=7) and argument.
+/// The argument is always written in its shortest form (RFC 8949 section 4.2.1).
+#[inline]
+pub fn write_head(bytes: &mut Vec<u8>, major_type: u8, argument: u64) {
+    let major_type = major_type << 5;
+    match argument {
+        0..0x18 => bytes.push(major_type | argument as u8),
+        0x18..0x100 => {
+            bytes.push(major_type | 0x18);
+            bytes.push(argument as u8);
+        },
+        0x100..0x10000 => {
+            bytes.push(major_type | 0x19);
+            bytes.extend_from_slice(&(argument as u16).to_be_bytes());
+        },
+        0x10000..0x1_0000_0000 => {
+            bytes.push(major_type | 0x1a);
+            bytes.extend_from_slice(&(argument as u32).to_be_bytes());
+        },
+        _ => {
+            bytes.push(major_type | 0x1b);
+            bytes.extend_from_slice(&argument.to_be_bytes());
+        },
+    }
+}
+
+/// Writes a map, sorted by encoded key if the options ask for deterministic output.
+pub fn write_map<'a, K, V>(bytes: &mut Vec<u8>, entries: impl ExactSizeIterator<Item = (&'a K, &'a V)>, options: &EncodeOptions)
+where
+    K: Cbor + 'a,
+    V: Cbor + 'a,
+{
+    write_head(bytes, 5, entries.len() as u64);
+    if options.deterministic {
+        let mut encoded: Vec<_> = entries
+            .map(|(key, value)| (key.to_cbor_bytes_with(options), value.to_cbor_bytes_with(options)))
+            .collect();
+        encoded.sort_unstable();
+        for (key, value) in encoded {
+            bytes.extend_from_slice(&key);
+            bytes.extend_from_slice(&value);
+        }
+    } else {
+        for (key, value) in entries {
+            key.encode_into(bytes, options);
+            value.encode_into(bytes, options);
+        }
+    }
+}
+
+/// Writes a set as an array, sorted by encoding if the options ask for deterministic output.
+pub fn write_set<'a, T>(bytes: &mut Vec<u8>, items: impl ExactSizeIterator<Item = &'a T>, options: &EncodeOptions)
+where
+    T: Cbor + 'a,
+{
+    write_head(bytes, 4, items.len() as u64);
+    if options.deterministic {
+        let mut encoded: Vec<_> = items.map(|item| item.to_cbor_bytes_with(options)).collect();
+        encoded.sort_unstable();
+        for item in encoded {
+            bytes.extend_from_slice(&item);
+        }
+    } else {
+        for item in items {
+            item.encode_into(bytes, options);
+        }
+    }
+}
+
+/// Reads the argument of the head at the start of `bytes`, whatever its major type.
+/// Returns the argument and the size of the head in bytes.
+#[inline]
+pub fn read_argument(bytes: &[u8]) -> Result<(u64, usize), CborError> {
+    let initial_byte = byte_at(bytes, 0)?;
+    match initial_byte & 0x1f {
+        info @ 0x00..0x18 => Ok((info as u64, 1)),
+        0x18 => Ok((byte_at(bytes, 1)? as u64, 2)),
+        0x19 => Ok((u16::from_be_bytes(array_at(bytes, 1)?) as u64, 3)),
+        0x1a => Ok((u32::from_be_bytes(array_at(bytes, 1)?) as u64, 5)),
+        0x1b => Ok((u64::from_be_bytes(array_at(bytes, 1)?), 9)),
+        _ => Err(CborErrorKind::IllFormed(format!("Byte {:x} is not followed by a definite argument", initial_byte)).into()),
+    }
+}
+
+/// Reads an integer of major type 0 or 1 of any head width.
+/// The result is widened to i128 so that the full range -2^64..2^64 fits.
+#[inline]
+pub fn read_integer(bytes: &[u8]) -> Result<(i128, usize), CborError> {
+    let initial_byte = byte_at(bytes, 0)?;
+    match expected_data_item(initial_byte) {
+        DataItem::SmallInt(byte) => Ok((byte as i128, 1)),
+        DataItem::SmallNegInt(byte) => Ok((byte as i128, 1)),
+        DataItem::Uint1 | DataItem::Uint2 | DataItem::Uint4 | DataItem::Uint8 => {
+            let (argument, bytes_read) = read_argument(bytes)?;
+            Ok((argument as i128, bytes_read))
+        },
+        DataItem::NegUint1 | DataItem::NegUint2 | DataItem::NegUint4 | DataItem::NegUint8 => {
+            let (argument, bytes_read) = read_argument(bytes)?;
+            Ok((-1 - argument as i128, bytes_read))
+        },
+        _ => Err(CborErrorKind::TypeMismatch { expected: "integer", found: initial_byte }.into())
+    }
+}
+
+/// Reads the head of a tag of any width and returns the tag number and the size of the head.
+/// The tagged data item follows the head.
+#[inline]
+pub fn read_tag(bytes: &[u8]) -> Result<(u64, usize), CborError> {
+    let initial_byte = byte_at(bytes, 0)?;
+    match expected_data_item(initial_byte) {
+        DataItem::Tag(tag) => Ok((tag as u64, 1)),
+        DataItem::UnsignedBigNum => Ok((2, 1)),
+        DataItem::NegativeBigNum => Ok((3, 1)),
+        DataItem::Tag1 | DataItem::Tag2 | DataItem::Tag4 | DataItem::Tag8 => read_argument(bytes),
+        _ => Err(CborErrorKind::TypeMismatch { expected: "tag", found: initial_byte }.into())
+    }
+}
+
+
+/// Reads the length argument of a string, array or map head of any width.
+/// Returns the length and the size of the head in bytes.
+#[inline]
+pub fn read_length(bytes: &[u8]) -> Result<(usize, usize), CborError> {
+    let (argument, head_len) = read_argument(bytes)?;
+    match usize::try_from(argument) {
+        Ok(data_len) => Ok((data_len, head_len)),
+        Err(_) => Err(CborErrorKind::LengthTooLarge(argument).into()),
+    }
+}
+
+/// Reads an indefinite-length byte string (major type 2) or text string (major type 3) and
+/// concatenates its chunks. Every chunk must be a definite-length string of the same major type,
+/// and text chunks must be valid utf-8 on their own.
+/// Returns the content and the total number of bytes read, including the "break".
+pub fn read_chunked_string(bytes: &[u8], major_type: u8) -> Result<(Vec<u8>, usize), CborError> {
+    let mut content = Vec::new();
+    let mut i = 1;
+    loop {
+        let chunk_byte = byte_at(bytes, i)?;
+        if chunk_byte == 0xff {
+            break
+        }
+        if chunk_byte >> 5 != major_type || chunk_byte & 0x1f == 0x1f {
+            return Err(CborError::from(CborErrorKind::IllFormed(format!("Byte {:x} is not a valid chunk of an indefinite-length string of major type {}", chunk_byte, major_type))).at(i))
+        }
+        let (data_len, head_len) = read_length(&bytes[i..]).map_err(|e| e.at(i))?;
+        let chunk = bytes_at(bytes, i+head_len, data_len)?;
+        if major_type == 3 {
+            if let Err(e) = std::str::from_utf8(chunk) {
+                return Err(CborError::from(CborErrorKind::InvalidUtf8).at(i + head_len + e.valid_up_to()))
             }
-            DataItem::ByteString8 => {
-                let data_len = u64::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
-                v.extend_from_slice(&bytes[9..9+data_len]);
-                bytes_read = 9+data_len;
-            },
-            _ => return Err(CborError::Unexpected("Error from byteslice_from_cbor() function".to_owned()))
-        };
-        Ok((v, bytes_read))
+        }
+        content.extend_from_slice(chunk);
+        i += head_len + data_len;
+    }
+    Ok((content, i+1)) // The +1 is to account for the "break"
+}
+
+/// Decodes every item of the array at the start of `bytes` and hands them to `push` in order.
+/// Returns the total number of bytes read, including the head.
+pub fn read_array_items<T, F>(bytes: &[u8], push: F) -> Result<usize, CborError>
+where 
+    T: Cbor,
+    F: FnMut(T),
+{
+    read_array_items_with(bytes, &mut Limits::default(), <T as Cbor>::from_cbor_bytes_with, push)
+}
+
+/// Like `read_array_items`, within `limits`, with `decode` decoding each item from the bytes
+/// starting at it. Each item counts the size of a `T` against the allocation limit.
+pub fn read_array_items_with<'de, T, D, F>(bytes: &'de [u8], limits: &mut Limits, mut decode: D, mut push: F) -> Result<usize, CborError>
+where 
+    D: FnMut(&'de [u8], &mut Limits) -> Result<(T, usize), CborError>,
+    F: FnMut(T),
+{
+    let (data_len, mut i) = match expected_data_item(byte_at(bytes, 0)?) {
+        DataItem::SmallArray(_)
+        | DataItem::Array1
+        | DataItem::Array2
+        | DataItem::Array4
+        | DataItem::Array8 => {
+            let (data_len, head_len) = read_length(bytes)?;
+            limits.check_collection_len(data_len)?;
+            (Some(data_len), head_len)
+        },
+        DataItem::TerminatedArray => (None, 1),
+        _ => return Err(CborErrorKind::TypeMismatch { expected: "array", found: bytes[0] }.into()),
+    };
+    limits.nested(|limits| {
+        let mut index = 0;
+        loop {
+            match data_len {
+                Some(data_len) if index == data_len => break,
+                None if byte_at(bytes, i)? == 0xff => {
+                    i += 1; // The "break"
+                    break
+                },
+                None => limits.check_collection_len(index + 1).map_err(|e| e.at(i))?,
+                _ => (),
+            }
+            limits.allocate(std::mem::size_of::<T>()).map_err(|e| e.at(i))?;
+            let (t, bytes_read) = decode(bytes_from(bytes, i)?, limits)
+                .map_err(|e| e.at(i).in_index(index))?;
+            push(t);
+            i += bytes_read;
+            index += 1;
+        }
+        Ok(i)
+    })
+}
+
+/// Decodes every key/value pair of the map at the start of `bytes` and hands them to `insert` in order.
+/// Returns the total number of bytes read, including the head.
+pub fn read_map_entries<K, V, F>(bytes: &[u8], insert: F) -> Result<usize, CborError>
+where 
+    K: Cbor,
+    V: Cbor,
+    F: FnMut(K, V),
+{
+    read_map_entries_with(bytes, &mut Limits::default(), <K as Cbor>::from_cbor_bytes_with, <V as Cbor>::from_cbor_bytes_with, insert)
 }
 
+/// Like `read_map_entries`, within `limits`, with `decode_key` and `decode_value` decoding each
+/// key and value from the bytes starting at it. Each entry counts the size of a `K` and a `V`
+/// against the allocation limit.
+pub fn read_map_entries_with<'de, K, V, DK, DV, F>(
+    bytes: &'de [u8],
+    limits: &mut Limits,
+    mut decode_key: DK,
+    mut decode_value: DV,
+    mut insert: F,
+) -> Result<usize, CborError>
+where 
+    DK: FnMut(&'de [u8], &mut Limits) -> Result<(K, usize), CborError>,
+    DV: FnMut(&'de [u8], &mut Limits) -> Result<(V, usize), CborError>,
+    F: FnMut(K, V),
+{
+    let (data_len, mut i) = read_map_head(bytes, limits)?;
+    limits.nested(|limits| {
+        let mut index = 0;
+        while !at_map_end(bytes, &mut i, data_len, index, limits)? {
+            limits.allocate(std::mem::size_of::<K>() + std::mem::size_of::<V>()).map_err(|e| e.at(i))?;
+            let (key, value, bytes_read) = read_map_entry(bytes, i, index, limits, &mut decode_key, &mut decode_value)?;
+            insert(key, value);
+            i += bytes_read;
+            index += 1;
+        }
+        Ok(i)
+    })
+}
+
+/// Reads the head of a map and checks its length against `limits`.
+/// Returns the number of entries, or None if it has indefinite length, and the size of the head.
+fn read_map_head(bytes: &[u8], limits: &Limits) -> Result<(Option<usize>, usize), CborError> {
+    match expected_data_item(byte_at(bytes, 0)?) {
+        DataItem::SmallMap(_)
+        | DataItem::Map1
+        | DataItem::Map2
+        | DataItem::Map4
+        | DataItem::Map8 => {
+            let (data_len, head_len) = read_length(bytes)?;
+            limits.check_collection_len(data_len)?;
+            Ok((Some(data_len), head_len))
+        },
+        DataItem::TerminatedMap => Ok((None, 1)),
+        _ => Err(CborErrorKind::TypeMismatch { expected: "map", found: bytes[0] }.into()),
+    }
+}
+
+/// Whether the map whose next entry would start at `i` has ended after `index` entries.
+/// Moves `i` past the "break" of an indefinite-length map.
+fn at_map_end(bytes: &[u8], i: &mut usize, data_len: Option<usize>, index: usize, limits: &Limits) -> Result<bool, CborError> {
+    match data_len {
+        Some(data_len) => Ok(index == data_len),
+        None if byte_at(bytes, *i)? == 0xff => {
+            *i += 1;
+            Ok(true)
+        },
+        None => {
+            limits.check_collection_len(index + 1).map_err(|e| e.at(*i))?;
+            Ok(false)
+        },
+    }
+}
+
+/// Walks the entries of a map with text keys, as written for structs, within `limits`.
+/// `field` is called with each key and the bytes starting at its value, and returns how many
+/// bytes it read, or None if it does not know the key. Values of unknown keys are skipped.
+/// Returns the total number of bytes read, including the head.
+pub fn read_struct_fields<'de, F>(bytes: &'de [u8], limits: &mut Limits, mut field: F) -> Result<usize, CborError>
+where 
+    F: FnMut(&str, &'de [u8], &mut Limits) -> Result<Option<usize>, CborError>,
+{
+    let (data_len, mut i) = read_map_head(bytes, limits)?;
+    limits.nested(|limits| {
+        let mut index = 0;
+        while !at_map_end(bytes, &mut i, data_len, index, limits)? {
+            let (key, key_bytes_read) = <Cow<str> as CborDecode>::from_cbor_slice_with(bytes_from(bytes, i)?, limits)
+                .map_err(|e| e.at(i).in_index(index))?;
+            let value_start = i + key_bytes_read;
+            let value_bytes = bytes_from(bytes, value_start)?;
+            let value_bytes_read = match field(&key, value_bytes, limits) {
+                Ok(Some(value_bytes_read)) => Ok(value_bytes_read),
+                Ok(None) => skip_item(value_bytes),
+                Err(e) => Err(e),
+            }.map_err(|e| e.at(value_start).in_key(key.into_owned()))?;
+            i = value_start + value_bytes_read;
+            index += 1;
+        }
+        Ok(i)
+    })
+}
+
+/// Walks the items of an array that must hold exactly `len` items, as written for tuple structs,
+/// within `limits`. `item` is called with the index and the bytes starting at each item, and
+/// returns how many bytes it read. Returns the total number of bytes read, including the head.
+pub fn read_tuple_items<'de, F>(bytes: &'de [u8], limits: &mut Limits, len: usize, mut item: F) -> Result<usize, CborError>
+where 
+    F: FnMut(usize, &'de [u8], &mut Limits) -> Result<usize, CborError>,
+{
+    let (indefinite, mut i) = match expected_data_item(byte_at(bytes, 0)?) {
+        DataItem::SmallArray(_)
+        | DataItem::Array1
+        | DataItem::Array2
+        | DataItem::Array4
+        | DataItem::Array8 => {
+            let (data_len, head_len) = read_length(bytes)?;
+            if data_len != len {
+                return Err(CborErrorKind::Unexpected(format!("Expected an array of {} items. Got {}", len, data_len)).into())
+            }
+            (false, head_len)
+        },
+        DataItem::TerminatedArray => (true, 1),
+        _ => return Err(CborErrorKind::TypeMismatch { expected: "array", found: bytes[0] }.into()),
+    };
+    limits.nested(|limits| {
+        for index in 0..len {
+            if indefinite && byte_at(bytes, i)? == 0xff {
+                return Err(CborError::from(CborErrorKind::Unexpected(format!("Expected an array of {} items. Got {}", len, index))).at(i))
+            }
+            i += item(index, bytes_from(bytes, i)?, limits).map_err(|e| e.at(i).in_index(index))?;
+        }
+        Ok(())
+    })?;
+    if indefinite {
+        if byte_at(bytes, i)? != 0xff {
+            return Err(CborError::from(CborErrorKind::Unexpected(format!("Expected an array of {} items. Got more", len))).at(i))
+        }
+        i += 1;
+    }
+    Ok(i)
+}
+
+/// Reads an enum variant written as a tag around its payload, within `limits`.
+/// `variant` is called with the tag number and the bytes starting at the payload, and returns
+/// the decoded value and how many bytes of payload it read.
+pub fn read_enum_tag<'de, T, F>(bytes: &'de [u8], limits: &mut Limits, variant: F) -> Result<(T, usize), CborError>
+where 
+    F: FnOnce(u64, &'de [u8], &mut Limits) -> Result<(T, usize), CborError>,
+{
+    let (tag, head_len) = read_tag(bytes)?;
+    let (t, bytes_read) = limits.nested(|limits| variant(tag, bytes_from(bytes, head_len)?, limits))
+        .map_err(|e| e.at(head_len))?;
+    Ok((t, head_len + bytes_read))
+}
+
+/// Reads an externally tagged enum variant within `limits`: either a text string naming a unit
+/// variant, or a map with a single entry from the variant name to its payload.
+/// `variant` is called with the name and the bytes starting at the payload if there is one, and
+/// returns the decoded value and how many bytes of payload it read.
+pub fn read_enum_map<'de, T, F>(bytes: &'de [u8], limits: &mut Limits, variant: F) -> Result<(T, usize), CborError>
+where 
+    F: FnOnce(&str, Option<&'de [u8]>, &mut Limits) -> Result<(T, usize), CborError>,
+{
+    let initial_byte = byte_at(bytes, 0)?;
+    match initial_byte >> 5 {
+        3 => {
+            let (name, bytes_read) = <Cow<str> as CborDecode>::from_cbor_slice_with(bytes, limits)?;
+            let (t, _) = variant(&name, None, limits)?;
+            Ok((t, bytes_read))
+        },
+        5 => {
+            let indefinite = initial_byte == 0xbf;
+            let mut i = if indefinite {
+                1
+            } else {
+                let (data_len, head_len) = read_length(bytes)?;
+                if data_len != 1 {
+                    return Err(CborErrorKind::Unexpected(format!("Expected a map with a single variant. Got {} entries", data_len)).into())
+                }
+                head_len
+            };
+            let (name, name_bytes_read) = <Cow<str> as CborDecode>::from_cbor_slice_with(bytes_from(bytes, i)?, limits)
+                .map_err(|e| e.at(i).in_index(0))?;
+            i += name_bytes_read;
+            let (t, bytes_read) = limits.nested(|limits| variant(&name, Some(bytes_from(bytes, i)?), limits))
+                .map_err(|e| e.at(i).in_key(name.into_owned()))?;
+            i += bytes_read;
+            if indefinite {
+                if byte_at(bytes, i)? != 0xff {
+                    return Err(CborError::from(CborErrorKind::Unexpected("Expected a map with a single variant. Got more entries".to_owned())).at(i))
+                }
+                i += 1;
+            }
+            Ok((t, i))
+        },
+        _ => Err(CborErrorKind::TypeMismatch { expected: "text string or map", found: initial_byte }.into()),
+    }
+}
+
+/// Decodes the map entry that starts at `i`. `index` is the position of the entry in the map.
+/// Returns the key, the value and the number of bytes read.
+fn read_map_entry<'de, K, V, DK, DV>(
+    bytes: &'de [u8],
+    i: usize,
+    index: usize,
+    limits: &mut Limits,
+    decode_key: &mut DK,
+    decode_value: &mut DV,
+) -> Result<(K, V, usize), CborError>
+where 
+    DK: FnMut(&'de [u8], &mut Limits) -> Result<(K, usize), CborError>,
+    DV: FnMut(&'de [u8], &mut Limits) -> Result<(V, usize), CborError>,
+{
+    let (key, key_bytes_read) = decode_key(bytes_from(bytes, i)?, limits)
+        .map_err(|e| e.at(i).in_index(index))?;
+    let value_start = i + key_bytes_read;
+    let (value, value_bytes_read) = decode_value(bytes_from(bytes, value_start)?, limits)
+        .map_err(|e| e.at(value_start).in_key(describe_key(&bytes[i..value_start])))?;
+    Ok((key, value, key_bytes_read + value_bytes_read))
+}
 
 impl Cbor for bool {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
         match self {
-            false => vec![0xf4],
-            true => vec![0xf5],
+            false => bytes.push(0xf4),
+            true => bytes.push(0xf5),
         }
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
+        match expected_data_item(byte_at(bytes, 0)?) {
             DataItem::Bool(b) => Ok((b, 1)),
-            _ => return Err(CborError::Unexpected("Error from bool implementation".to_owned()))
+            _ => Err(CborErrorKind::TypeMismatch { expected: "bool", found: bytes[0] }.into())
         }
     }
 }
 
 impl Cbor for u8 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        match self {
-            0x00..0x18 => bytes.push(*self),
-            _ => {
-                bytes.push(0x18);
-                bytes.push(*self);
-            }
-        }
-        bytes
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
+        write_head(bytes, 0, *self as u64);
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallInt(byte) => Ok((byte, 1)),
-            DataItem::Uint1 => Ok((bytes[1], 2)),
-            _ => return Err(CborError::Unexpected("Error from u8 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match u8::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "u8" }.into())
         }
     }
 }
 
 impl Cbor for u16 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        vec![
-            0x19,
-            self.to_be_bytes()[0],
-            self.to_be_bytes()[1]
-        ]
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
+        write_head(bytes, 0, *self as u64);
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Uint2 => Ok((
-                u16::from_be_bytes([
-                    bytes[1],
-                    bytes[2]
-                ]),
-                3
-            )),
-            _ => return Err(CborError::Unexpected("Error from u16 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match u16::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "u16" }.into())
         }
     }
 }
 
 impl Cbor for u32 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        vec![
-            0x1a,
-            self.to_be_bytes()[0],
-            self.to_be_bytes()[1],
-            self.to_be_bytes()[2],
-            self.to_be_bytes()[3]
-        ]
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
+        write_head(bytes, 0, *self as u64);
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Uint4 => Ok(
-                (u32::from_be_bytes([
-                    bytes[1],
-                    bytes[2],
-                    bytes[3],
-                    bytes[4]
-                ]), 
-                5
-            )
-            ),
-            _ => return Err(CborError::Unexpected("Error from u32 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match u32::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "u32" }.into())
         }
     }
 }
 
 impl Cbor for u64 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        vec![
-            0x1b,
-            self.to_be_bytes()[0],
-            self.to_be_bytes()[1],
-            self.to_be_bytes()[2],
-            self.to_be_bytes()[3],
-            self.to_be_bytes()[4],
-            self.to_be_bytes()[5],
-            self.to_be_bytes()[6],
-            self.to_be_bytes()[7]
-        ]
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
+        write_head(bytes, 0, *self);
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Uint8 => Ok((u64::from_be_bytes([
-                bytes[1],
-                bytes[2],
-                bytes[3],
-                bytes[4],
-                bytes[5],
-                bytes[6],
-                bytes[7],
-                bytes[8]
-            ]), 
-            9)),
-            _ => return Err(CborError::Unexpected("Error from u64 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match u64::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "u64" }.into())
         }
     }
 }
 
 impl Cbor for usize {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let num = *self as u64;
-        vec![
-            0x1b,
-            num.to_be_bytes()[0],
-            num.to_be_bytes()[1],
-            num.to_be_bytes()[2],
-            num.to_be_bytes()[3],
-            num.to_be_bytes()[4],
-            num.to_be_bytes()[5],
-            num.to_be_bytes()[6],
-            num.to_be_bytes()[7]
-        ]
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
+        write_head(bytes, 0, *self as u64);
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Uint8 => Ok((u64::from_be_bytes([
-                bytes[1],
-                bytes[2],
-                bytes[3],
-                bytes[4],
-                bytes[5],
-                bytes[6],
-                bytes[7],
-                bytes[8]
-            ]) as usize, 
-            9)),
-            _ => return Err(CborError::Unexpected("Error from usize implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match usize::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "usize" }.into())
         }
     }
 }
 
 impl Cbor for i8 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
         if *self < 0 {
-            match self {
-                0x20..0x38 => bytes.push(*self as u8),
-                _ => {
-                    bytes.push(0x38);
-                    bytes.push((-self.abs()).to_be_bytes()[0]);
-                },
-            };
+            write_head(bytes, 1, !*self as u64);
         } else {
-            bytes.extend_from_slice(&(*self as u8).to_cbor_bytes());
+            write_head(bytes, 0, *self as u64);
         }
-        bytes
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallNegInt(byte) => Ok((byte, 1)),
-            DataItem::NegUint1 => Ok((i8::from_be_bytes([
-                bytes[1]
-            ]), 2)),
-            _ => return Err(CborError::Unexpected("Error from i8 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match i8::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "i8" }.into())
         }
     }
 }
 
 impl Cbor for i16 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
         if *self < 0 {
-            let s = - self.abs();
-            vec![
-                0x39,
-                s.to_be_bytes()[0],
-                s.to_be_bytes()[1]
-            ]
+            write_head(bytes, 1, !*self as u64);
         } else {
-            (*self as u16).to_cbor_bytes()
+            write_head(bytes, 0, *self as u64);
         }
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Uint2 => {
-                let (num, bytes_read) = <u16 as Cbor>::from_cbor_bytes(bytes)?;
-                Ok((num as i16, bytes_read))
-            },
-            DataItem::NegUint2 => Ok((i16::from_be_bytes([
-                bytes[1], 
-                bytes[2]
-            ]), 3)),
-            _ => return Err(CborError::Unexpected("Error from i16 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match i16::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "i16" }.into())
         }
     }
 }
 
 impl Cbor for i32 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
         if *self < 0 {
-            let s = - self.abs();
-            vec![
-                0x3a,
-                s.to_be_bytes()[0],
-                s.to_be_bytes()[1],
-                s.to_be_bytes()[2],
-                s.to_be_bytes()[3],
-            ]
+            write_head(bytes, 1, !*self as u64);
         } else {
-            (*self as u32).to_cbor_bytes()
+            write_head(bytes, 0, *self as u64);
         }
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Uint4 => {
-                let (num, bytes_read) = <u32 as Cbor>::from_cbor_bytes(bytes)?;
-                Ok((num as i32, bytes_read))
-            },
-            DataItem::NegUint4 => Ok((i32::from_be_bytes([
-                bytes[1], 
-                bytes[2],
-                bytes[3],
-                bytes[4],
-            ]), 5)),
-            _ => return Err(CborError::Unexpected("Error from i32 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match i32::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "i32" }.into())
         }
     }
 }
 
 impl Cbor for i64 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
         if *self < 0 {
-            let s = - self.abs();
-            vec![
-                0x3a,
-                s.to_be_bytes()[0],
-                s.to_be_bytes()[1],
-                s.to_be_bytes()[2],
-                s.to_be_bytes()[3],
-                s.to_be_bytes()[4],
-                s.to_be_bytes()[5],
-                s.to_be_bytes()[6],
-                s.to_be_bytes()[7],
-            ]
+            write_head(bytes, 1, !*self as u64);
         } else {
-            (*self as u32).to_cbor_bytes()
+            write_head(bytes, 0, *self as u64);
         }
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Uint8 => {
-                let (num, bytes_read) = <u64 as Cbor>::from_cbor_bytes(bytes)?;
-                Ok((num as i64, bytes_read))
-            },
-            DataItem::NegUint4 => Ok((i64::from_be_bytes([
-                bytes[1], 
-                bytes[2],
-                bytes[3],
-                bytes[4],
-                bytes[5],
-                bytes[6],
-                bytes[7],
-                bytes[8],
-            ]), 9)),
-            _ => return Err(CborError::Unexpected("Error from i64 implementation".to_owned()))
+        let (num, bytes_read) = read_integer(bytes)?;
+        match i64::try_from(num) {
+            Ok(num) => Ok((num, bytes_read)),
+            Err(_) => Err(CborErrorKind::IntegerOverflow { value: num, target: "i64" }.into())
         }
     }
 }
 
 impl Cbor for f32 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        vec![
-            0xfa,
-            self.to_be_bytes()[0],
-            self.to_be_bytes()[1],
-            self.to_be_bytes()[2],
-            self.to_be_bytes()[3]
-        ]
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        match options.float_encoding {
+            FloatEncoding::Native => {
+                bytes.push(0xfa);
+                bytes.extend_from_slice(&self.to_be_bytes());
+            },
+            FloatEncoding::Shortest => write_shortest_float(bytes, *self as f64),
+        }
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let (float, bytes_read) = read_float(bytes)?;
+        if (float as f32) as f64 == float || float.is_nan() {
+            Ok((float as f32, bytes_read))
+        } else {
+            Err(CborErrorKind::Unexpected(format!("{} does not fit in f32 without losing precision", float)).into())
+        }
+    }
+}
+
+impl Cbor for f64 {
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        match options.float_encoding {
+            FloatEncoding::Native => {
+                bytes.push(0xfb);
+                bytes.extend_from_slice(&self.to_be_bytes());
+            },
+            FloatEncoding::Shortest => write_shortest_float(bytes, *self),
+        }
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        read_float(bytes)
+    }
+}
+
+/// Reads a half, single or double precision float, widened to f64.
+#[inline]
+pub fn read_float(bytes: &[u8]) -> Result<(f64, usize), CborError> {
+    match expected_data_item(byte_at(bytes, 0)?) {
+        DataItem::Float2 => Ok((f16_to_f64(u16::from_be_bytes(array_at(bytes, 1)?)), 3)),
+        DataItem::Float4 => Ok((f32_to_f64(f32::from_be_bytes(array_at(bytes, 1)?)), 5)),
+        DataItem::Float8 => Ok((f64::from_be_bytes(array_at(bytes, 1)?), 9)),
+        _ => Err(CborErrorKind::TypeMismatch { expected: "float", found: bytes[0] }.into())
+    }
+}
+
+/// Writes a float in the shortest of half, single or double precision that holds it exactly.
+/// NaN is written as the half precision quiet NaN.
+pub fn write_shortest_float(bytes: &mut Vec<u8>, float: f64) {
+    if float.is_nan() {
+        bytes.extend_from_slice(&[0xf9, 0x7e, 0x00]);
+    } else if let Some(half) = f64_to_f16(float) {
+        bytes.push(0xf9);
+        bytes.extend_from_slice(&half.to_be_bytes());
+    } else if (float as f32) as f64 == float {
+        bytes.push(0xfa);
+        bytes.extend_from_slice(&(float as f32).to_be_bytes());
+    } else {
+        bytes.push(0xfb);
+        bytes.extend_from_slice(&float.to_be_bytes());
+    }
+}
+
+/// Writes a float in `width`, or in the narrowest wider precision that holds it exactly.
+/// NaN payloads are kept.
+fn write_float_in(bytes: &mut Vec<u8>, float: f64, width: FloatWidth) {
+    let bits = float.to_bits();
+    if width == FloatWidth::Half {
+        let half = if float.is_nan() {
+            narrow_nan(bits, 42).map(|mantissa| ((bits >> 48) & 0x8000) as u16 | 0x7c00 | mantissa as u16)
+        } else {
+            f64_to_f16(float)
+        };
+        if let Some(half) = half {
+            bytes.push(0xf9);
+            bytes.extend_from_slice(&half.to_be_bytes());
+            return
+        }
+    }
+    if width != FloatWidth::Double {
+        let single = if float.is_nan() {
+            narrow_nan(bits, 29).map(|mantissa| ((bits >> 32) & 0x8000_0000) as u32 | 0x7f80_0000 | mantissa as u32)
+        } else {
+            Some(float as f32).filter(|single| *single as f64 == float).map(f32::to_bits)
+        };
+        if let Some(single) = single {
+            bytes.push(0xfa);
+            bytes.extend_from_slice(&single.to_be_bytes());
+            return
+        }
+    }
+    bytes.push(0xfb);
+    bytes.extend_from_slice(&bits.to_be_bytes());
+}
+
+/// The mantissa of a NaN with its low `shift` bits dropped, if they are all zero.
+fn narrow_nan(bits: u64, shift: u32) -> Option<u64> {
+    let mantissa = bits & 0x000f_ffff_ffff_ffff;
+    (mantissa & ((1 << shift) - 1) == 0).then_some(mantissa >> shift)
+}
+
+/// Widens an f32 to f64, keeping NaN payloads bit for bit.
+fn f32_to_f64(single: f32) -> f64 {
+    if single.is_nan() {
+        let bits = single.to_bits() as u64;
+        f64::from_bits(((bits & 0x8000_0000) << 32) | (0x7ff << 52) | ((bits & 0x7f_ffff) << 29))
+    } else {
+        single as f64
+    }
+}
+
+/// Widens an IEEE 754 half-precision float to f64 (RFC 8949 appendix D). NaN payloads are kept.
+pub fn f16_to_f64(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        0x1f => if mantissa == 0.0 { f64::INFINITY } else { f64::from_bits((0x7ff << 52) | ((half & 0x3ff) as u64) << 42) },
+        _ => (1024.0 + mantissa) * 2f64.powi(exponent as i32 - 25),
+    };
+    if half & 0x8000 != 0 { -magnitude } else { magnitude }
+}
+
+/// Narrows a float to IEEE 754 half precision, if half precision holds it exactly.
+/// Returns None for NaN, whose payload can't always be kept.
+pub fn f64_to_f16(float: f64) -> Option<u16> {
+    let bits = float.to_bits();
+    let sign = ((bits >> 48) & 0x8000) as u16;
+    if float.is_nan() {
+        return None
+    }
+    if float.is_infinite() {
+        return Some(sign | 0x7c00)
+    }
+    if float == 0.0 {
+        return Some(sign)
+    }
+    let exponent = ((bits >> 52) & 0x7ff) as i32 - 1023;
+    let mantissa = bits & 0x000f_ffff_ffff_ffff;
+    match exponent {
+        // Normal half: 10 bits of mantissa are kept
+        -14..=15 => {
+            if mantissa & ((1 << 42) - 1) != 0 {
+                return None
+            }
+            Some(sign | (((exponent + 15) as u16) << 10) | (mantissa >> 42) as u16)
+        },
+        // Subnormal half: the value is a multiple of 2^-24
+        -24..=-15 => {
+            let shift = 28 - exponent;
+            let significand = mantissa | (1 << 52);
+            if significand & ((1 << shift) - 1) != 0 {
+                return None
+            }
+            Some(sign | (significand >> shift) as u16)
+        },
+        _ => None,
+    }
+}
+
+impl Cbor for String {
+    fn encode_into(&self, bytes: &mut Vec<u8>, _options: &EncodeOptions) {
+        write_head(bytes, 3, self.len() as u64);
+        bytes.extend_from_slice(self.as_bytes());
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let (text, bytes_read) = read_text(bytes)?;
+        limits.check_string_len(text.len())?;
+        limits.allocate(text.len())?;
+        Ok((text.into_owned(), bytes_read))
+    }
+}
+
+/// Reads a text string of any width. Definite-length strings are borrowed from `bytes`,
+/// indefinite-length ones are concatenated into a new String.
+pub fn read_text(bytes: &[u8]) -> Result<(Cow<'_, str>, usize), CborError> {
+    match expected_data_item(byte_at(bytes, 0)?) {
+        DataItem::SmallTextString(_)
+        | DataItem::TextString1
+        | DataItem::TextString2
+        | DataItem::TextString4
+        | DataItem::TextString8 => {
+            let (data_len, head_len) = read_length(bytes)?;
+            match std::str::from_utf8(bytes_at(bytes, head_len, data_len)?) {
+                Ok(text) => Ok((Cow::Borrowed(text), head_len+data_len)),
+                Err(e) => Err(CborError::from(CborErrorKind::InvalidUtf8).at(head_len + e.valid_up_to())),
+            }
+        },
+        DataItem::TerminatedTextString => {
+            let (content, bytes_read) = read_chunked_string(bytes, 3)?;
+            match String::from_utf8(content) {
+                Ok(text) => Ok((Cow::Owned(text), bytes_read)),
+                Err(_) => Err(CborErrorKind::InvalidUtf8.into()),
+            }
+        },
+        _ => Err(CborErrorKind::TypeMismatch { expected: "text string", found: bytes[0] }.into())
+    }
+}
+
+/// Reads a byte string of any width. Definite-length strings are borrowed from `bytes`,
+/// indefinite-length ones are concatenated into a new Vec.
+pub fn read_byte_string(bytes: &[u8]) -> Result<(Cow<'_, [u8]>, usize), CborError> {
+    match expected_data_item(byte_at(bytes, 0)?) {
+        DataItem::SmallByteString(_) 
+        | DataItem::ByteString1 
+        | DataItem::ByteString2 
+        | DataItem::ByteString4 
+        | DataItem::ByteString8 => {
+            let (data_len, head_len) = read_length(bytes)?;
+            Ok((Cow::Borrowed(bytes_at(bytes, head_len, data_len)?), head_len+data_len))
+        },
+        DataItem::TerminatedByteString => {
+            let (content, bytes_read) = read_chunked_string(bytes, 2)?;
+            Ok((Cow::Owned(content), bytes_read))
+        },
+        _ => Err(CborErrorKind::TypeMismatch { expected: "byte string", found: bytes[0] }.into())
+    }
+}
+
+
+impl<T> Cbor for Vec<T> where T: Cbor {
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        write_head(bytes, 4, self.len() as u64);
+        for item in self {
+            item.encode_into(bytes, options);
+        }
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let mut v = Vec::new();
+        let i = read_array_items_with(bytes, limits, <T as Cbor>::from_cbor_bytes_with, |t| { v.push(t); })?;
+        Ok((v, i))
+    }
+}
+
+impl<T> Cbor for HashSet<T> where T: Cbor + Hash + Eq {
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        write_set(bytes, self.iter(), options);
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let mut v = HashSet::new();
+        let i = read_array_items_with(bytes, limits, <T as Cbor>::from_cbor_bytes_with, |t| { v.insert(t); })?;
+        Ok((v, i))
+    }
+}
+
+impl<T> Cbor for BTreeSet<T> where T: Cbor + Hash + Eq + Ord {
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        write_set(bytes, self.iter(), options);
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let mut v = BTreeSet::new();
+        let i = read_array_items_with(bytes, limits, <T as Cbor>::from_cbor_bytes_with, |t| { v.insert(t); })?;
+        Ok((v, i))
+    }
+}
+
+impl<K, V> Cbor for HashMap<K, V> 
+where 
+    K: Cbor + Hash + Eq,
+    V: Cbor 
+{
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        write_map(bytes, self.iter(), options);
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let mut map = HashMap::new();
+        let i = read_map_entries_with(bytes, limits, <K as Cbor>::from_cbor_bytes_with, <V as Cbor>::from_cbor_bytes_with, |key, value| { map.insert(key, value); })?;
+        Ok((map, i))
+    }
+}
+
+
+impl<K, V> Cbor for BTreeMap<K, V> 
+where 
+    K: Cbor + Hash + Eq + Ord,
+    V: Cbor 
+{
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        write_map(bytes, self.iter(), options);
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let mut map = BTreeMap::new();
+        let i = read_map_entries_with(bytes, limits, <K as Cbor>::from_cbor_bytes_with, <V as Cbor>::from_cbor_bytes_with, |key, value| { map.insert(key, value); })?;
+        Ok((map, i))
+    }
+}
+
+/// A data item wrapped in a semantic tag (RFC 8949 section 3.4).
+/// Decoding accepts any tag number, so check `tag` before trusting `value`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tagged<T> {
+    pub tag: u64,
+    pub value: T,
+}
+
+impl<T> Tagged<T> {
+    pub fn new(tag: u64, value: T) -> Self {
+        Tagged { tag, value }
+    }
+}
+
+impl<T> Cbor for Tagged<T> where T: Cbor {
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        write_head(bytes, 6, self.tag);
+        self.value.encode_into(bytes, options);
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let (tag, head_len) = read_tag(bytes)?;
+        let (value, bytes_read) = limits.nested(|limits| <T as Cbor>::from_cbor_bytes_with(bytes_from(bytes, head_len)?, limits))
+            .map_err(|e| e.at(head_len))?;
+        Ok((Tagged { tag, value }, head_len + bytes_read))
+    }
+}
+
+/// Any well-formed CBOR data item, for documents whose shape isn't known at compile time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unsigned(u64),
+    /// The negative integer -1-n. Holds n, so that the full range down to -2^64 fits.
+    Negative(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    /// Entries are kept in the order they were decoded in. Keys can be any value.
+    Map(Vec<(Value, Value)>),
+    Tag(u64, Box<Value>),
+    Bool(bool),
+    Null,
+    Undefined,
+    /// Any simple value other than false, true, null and undefined.
+    Simple(u8),
+    /// A float and the precision it was decoded from, so that it can be written back unchanged.
+    Float(f64, FloatWidth),
+}
+
+impl Value {
+    /// Returns the value as an integer if it is one.
+    pub fn as_integer(&self) -> Option<i128> {
+        match self {
+            Value::Unsigned(num) => Some(*num as i128),
+            Value::Negative(num) => Some(-1 - *num as i128),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a str if it is a text string.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Looks up the value of the first entry with the given text key if this is a map.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter()
+                .find(|(k, _)| k.as_text() == Some(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl Cbor for Value {
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+        match self {
+            Value::Unsigned(num) => write_head(bytes, 0, *num),
+            Value::Negative(num) => write_head(bytes, 1, *num),
+            Value::Bytes(content) => {
+                write_head(bytes, 2, content.len() as u64);
+                bytes.extend_from_slice(content);
+            },
+            Value::Text(text) => {
+                write_head(bytes, 3, text.len() as u64);
+                bytes.extend_from_slice(text.as_bytes());
+            },
+            Value::Array(items) => {
+                write_head(bytes, 4, items.len() as u64);
+                for item in items {
+                    item.encode_into(bytes, options);
+                }
+            },
+            Value::Map(entries) => {
+                write_map(bytes, entries.iter().map(|(key, value)| (key, value)), options);
+            },
+            Value::Tag(tag, content) => {
+                write_head(bytes, 6, *tag);
+                content.encode_into(bytes, options);
+            },
+            Value::Bool(b) => b.encode_into(bytes, options),
+            Value::Null => bytes.push(0xf6),
+            Value::Undefined => bytes.push(0xf7),
+            Value::Simple(simple) => match simple {
+                0x00..0x18 => bytes.push(0xe0 | simple),
+                _ => {
+                    bytes.push(0xf8);
+                    bytes.push(*simple);
+                },
+            },
+            Value::Float(float, width) => match options.float_encoding {
+                FloatEncoding::Native => write_float_in(bytes, *float, *width),
+                FloatEncoding::Shortest => write_shortest_float(bytes, *float),
+            },
+        }
+    }
+
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
+        where 
+            Self: Sized 
+    {
+        let initial_byte = byte_at(bytes, 0)?;
+        if initial_byte < 0xe0 && (0x1c..0x1f).contains(&(initial_byte & 0x1f)) {
+            return Err(CborErrorKind::IllFormed(format!("Byte {:x} uses a reserved additional information value", initial_byte)).into())
+        }
+        match initial_byte >> 5 {
+            0 => {
+                let (num, bytes_read) = read_argument(bytes)?;
+                Ok((Value::Unsigned(num), bytes_read))
+            },
+            1 => {
+                let (num, bytes_read) = read_argument(bytes)?;
+                Ok((Value::Negative(num), bytes_read))
+            },
+            2 => {
+                let (content, bytes_read) = read_byte_string(bytes)?;
+                limits.check_string_len(content.len())?;
+                limits.allocate(content.len())?;
+                Ok((Value::Bytes(content.into_owned()), bytes_read))
+            },
+            3 => {
+                let (text, bytes_read) = <String as Cbor>::from_cbor_bytes_with(bytes, limits)?;
+                Ok((Value::Text(text), bytes_read))
+            },
+            4 => {
+                let mut items = Vec::new();
+                let bytes_read = read_array_items_with(bytes, limits, <Value as Cbor>::from_cbor_bytes_with, |item| items.push(item))?;
+                Ok((Value::Array(items), bytes_read))
+            },
+            5 => {
+                let mut entries = Vec::new();
+                let bytes_read = read_map_entries_with(
+                    bytes,
+                    limits,
+                    <Value as Cbor>::from_cbor_bytes_with,
+                    <Value as Cbor>::from_cbor_bytes_with,
+                    |key, value| entries.push((key, value)),
+                )?;
+                Ok((Value::Map(entries), bytes_read))
+            },
+            6 => {
+                let (tag, head_len) = read_tag(bytes)?;
+                let (content, bytes_read) = limits.nested(|limits| <Value as Cbor>::from_cbor_bytes_with(bytes_from(bytes, head_len)?, limits))
+                    .map_err(|e| e.at(head_len))?;
+                Ok((Value::Tag(tag, Box::new(content)), head_len + bytes_read))
+            },
+            _ => match expected_data_item(initial_byte) {
+                DataItem::Bool(b) => Ok((Value::Bool(b), 1)),
+                DataItem::Null => Ok((Value::Null, 1)),
+                DataItem::Undefined => Ok((Value::Undefined, 1)),
+                DataItem::Float2 | DataItem::Float4 | DataItem::Float8 => {
+                    let (float, bytes_read) = read_float(bytes)?;
+                    let width = match bytes_read {
+                        3 => FloatWidth::Half,
+                        5 => FloatWidth::Single,
+                        _ => FloatWidth::Double,
+                    };
+                    Ok((Value::Float(float, width), bytes_read))
+                },
+                DataItem::Stop => Err(CborErrorKind::IllFormed("Unexpected \"break\" outside of an indefinite-length item".to_owned()).into()),
+                _ => match initial_byte {
+                    0xe0..0xf4 => Ok((Value::Simple(initial_byte & 0x1f), 1)),
+                    0xf8 => match byte_at(bytes, 1)? {
+                        simple @ 0x20.. => Ok((Value::Simple(simple), 2)),
+                        simple => Err(CborError::from(CborErrorKind::IllFormed(format!("Simple value {} must be encoded in a single byte", simple))).at(1)),
+                    },
+                    _ => Err(CborErrorKind::IllFormed(format!("Byte {:x} is not a valid initial byte", initial_byte)).into()),
+                },
+            },
+        }
+    }
+}
+
+/// Decoding that can borrow from the input, so that `&'de str` and `&'de [u8]` point into the
+/// buffer instead of being copied. Derive it with `#[derive(CborDecode)]` for structs and enums
+/// holding borrowed fields. Every type with a built-in Cbor impl implements it too.
+/// Implementations provide `from_cbor_slice_with`.
+pub trait CborDecode<'de>: Sized {
+    fn from_cbor_slice(bytes: &'de [u8]) -> Result<(Self, usize), CborError> {
+        Self::from_cbor_slice_with(bytes, &mut Limits::default())
+    }
+
+    /// Decodes within `limits`, passing them on to nested values.
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError>;
+}
+
+/// Decodes the data item at the start of `bytes`, borrowing from it where `T` allows.
+#[inline]
+pub fn decode_cbor_borrowed<'de, T>(bytes: &'de [u8]) -> Result<T, CborError> where T: CborDecode<'de> {
+    let (t, _) = T::from_cbor_slice(bytes)?;
+    Ok(t)
+}
+
+/// Like `decode_cbor_borrowed`, within the given options.
+#[inline]
+pub fn decode_cbor_borrowed_with<'de, T>(bytes: &'de [u8], options: &DecodeOptions) -> Result<T, CborError> where T: CborDecode<'de> {
+    let (t, _) = T::from_cbor_slice_with(bytes, &mut Limits::new(*options))?;
+    Ok(t)
+}
+
+macro_rules! decode_owned {
+    ($($ty:ty),*) => {
+        $(
+            impl<'de> CborDecode<'de> for $ty {
+                fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+                    <$ty as Cbor>::from_cbor_bytes_with(bytes, limits)
+                }
+            }
+        )*
+    };
+}
+
+decode_owned!(bool, u8, u16, u32, u64, usize, i8, i16, i32, i64, f32, f64, String, Value, Item);
+
+impl<'de: 'a, 'a> CborDecode<'de> for &'a str {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        match read_text(bytes)? {
+            (Cow::Borrowed(text), bytes_read) => {
+                limits.check_string_len(text.len())?;
+                Ok((text, bytes_read))
+            },
+            (Cow::Owned(_), _) => Err(CborErrorKind::Unexpected("An indefinite length text string can't be borrowed. Use Cow<str>".to_owned()).into()),
+        }
+    }
+}
+
+impl<'de: 'a, 'a> CborDecode<'de> for &'a [u8] {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        match read_byte_string(bytes)? {
+            (Cow::Borrowed(content), bytes_read) => {
+                limits.check_string_len(content.len())?;
+                Ok((content, bytes_read))
+            },
+            (Cow::Owned(_), _) => Err(CborErrorKind::Unexpected("An indefinite length byte string can't be borrowed. Use Cow<[u8]>".to_owned()).into()),
+        }
+    }
+}
+
+impl<'de: 'a, 'a> CborDecode<'de> for Cow<'a, str> {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let (string, bytes_read) = read_text(bytes)?;
+        Ok((limits.check_string(string)?, bytes_read))
+    }
+}
+
+impl<'de: 'a, 'a> CborDecode<'de> for Cow<'a, [u8]> {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let (string, bytes_read) = read_byte_string(bytes)?;
+        Ok((limits.check_string(string)?, bytes_read))
+    }
+}
+
+impl<'de, T> CborDecode<'de> for Vec<T> where T: CborDecode<'de> {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let mut v = Vec::new();
+        let i = read_array_items_with(bytes, limits, T::from_cbor_slice_with, |t| { v.push(t); })?;
+        Ok((v, i))
     }
+}
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        match expected_data_item(bytes[0]) {
-            DataItem::Float4 => Ok((f32::from_be_bytes([
-                bytes[1],
-                bytes[2],
-                bytes[3],
-                bytes[4]
-            ]), 5)),
-            _ => return Err(CborError::Unexpected("Error from f32 implementation".to_owned()))
-        }
+impl<'de, T> CborDecode<'de> for HashSet<T> where T: CborDecode<'de> + Hash + Eq {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let mut set = HashSet::new();
+        let i = read_array_items_with(bytes, limits, T::from_cbor_slice_with, |t| { set.insert(t); })?;
+        Ok((set, i))
     }
 }
 
-impl Cbor for f64 {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        vec![
-            0xfb,
-            self.to_be_bytes()[0],
-            self.to_be_bytes()[1],
-            self.to_be_bytes()[2],
-            self.to_be_bytes()[3],
-            self.to_be_bytes()[4],
-            self.to_be_bytes()[5],
-            self.to_be_bytes()[6],
-            self.to_be_bytes()[7]
-        ]
+impl<'de, T> CborDecode<'de> for BTreeSet<T> where T: CborDecode<'de> + Ord {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let mut set = BTreeSet::new();
+        let i = read_array_items_with(bytes, limits, T::from_cbor_slice_with, |t| { set.insert(t); })?;
+        Ok((set, i))
     }
+}
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        match expected_data_item(bytes[0]) {
-            DataItem::Float8 => Ok((f64::from_be_bytes([
-                bytes[1],
-                bytes[2],
-                bytes[3],
-                bytes[4],
-                bytes[5],
-                bytes[6],
-                bytes[7],
-                bytes[8]
-            ]), 9)),
-            _ => return Err(CborError::Unexpected("Error from f64 implementation".to_owned()))
-        }
+impl<'de, K, V> CborDecode<'de> for HashMap<K, V> where K: CborDecode<'de> + Hash + Eq, V: CborDecode<'de> {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let mut map = HashMap::new();
+        let i = read_map_entries_with(bytes, limits, K::from_cbor_slice_with, V::from_cbor_slice_with, |key, value| { map.insert(key, value); })?;
+        Ok((map, i))
     }
 }
 
-impl Cbor for String {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        if self.len() < 24 {
-            bytes.push(0x60+self.len() as u8);
-            bytes.extend_from_slice(&self.as_bytes());
-        } else {
-            bytes.push(0x7b);
-            bytes.extend_from_slice(&self.len().to_be_bytes());
-            bytes.extend_from_slice(self.as_bytes());
-        }
-        bytes
+impl<'de, K, V> CborDecode<'de> for BTreeMap<K, V> where K: CborDecode<'de> + Ord, V: CborDecode<'de> {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let mut map = BTreeMap::new();
+        let i = read_map_entries_with(bytes, limits, K::from_cbor_slice_with, V::from_cbor_slice_with, |key, value| { map.insert(key, value); })?;
+        Ok((map, i))
     }
+}
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        let mut v = String::new();
-        let bytes_read;
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallTextString(byte) => {
-                let encoded_text = match std::str::from_utf8(&bytes[1..1+byte as usize]) {
-                    Ok(text) => text,
-                    Err(_) => return Err(CborError::IllFormed(format!("Decoded string is not valid utf-8"))),
-                };
-                v.push_str(encoded_text);
-                bytes_read = v.len() + 1;
-            }
-            DataItem::TextString8 => {
-                let data_len = u64::from_be_bytes([
-                    bytes[1],
-                    bytes[2],
-                    bytes[3],
-                    bytes[4],
-                    bytes[5],
-                    bytes[6],
-                    bytes[7],
-                    bytes[8]
-                    ]) as usize;
-                let encoded_text = match std::str::from_utf8(&bytes[9..9+data_len]) {
-                    Ok(text) => text,
-                    Err(_) => return Err(CborError::IllFormed(format!("Decoded string is not valid utf-8"))),
-                };
-                v.push_str(encoded_text);
-                bytes_read = v.len() + 9;
-            },
-            _ => return Err(CborError::Unexpected("Error from String implementation".to_owned()))
-        };
-        Ok((v, bytes_read))
+impl<'de, T> CborDecode<'de> for Tagged<T> where T: CborDecode<'de> {
+    fn from_cbor_slice_with(bytes: &'de [u8], limits: &mut Limits) -> Result<(Self, usize), CborError> {
+        let (tag, head_len) = read_tag(bytes)?;
+        let (value, bytes_read) = limits.nested(|limits| T::from_cbor_slice_with(bytes_from(bytes, head_len)?, limits))
+            .map_err(|e| e.at(head_len))?;
+        Ok((Tagged { tag, value }, head_len + bytes_read))
     }
 }
 
+/// Checks that the data item at the start of `bytes` is well-formed and that its text strings
+/// are valid utf-8, without decoding it. Returns the length of the item in bytes; anything
+/// after it is not looked at.
+pub fn validate(bytes: &[u8]) -> Result<usize, CborError> {
+    walk_item(bytes, true)
+}
 
-impl<T> Cbor for Vec<T> where T: Cbor {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut v = Vec::new();
-        if self.len() < 24 {
-            v.push(0x80 + self.len() as u8);
-        } else {
-            v.push(0x9b);
-            v.extend_from_slice(&self.len().to_be_bytes());
-        }
-        for item in self {
-            v.extend_from_slice(&item.to_cbor_bytes());
-        }
-        v
-    }
+/// Returns the length in bytes of the data item at the start of `bytes`, without decoding it.
+/// The item must be well-formed, but text strings are not checked for valid utf-8.
+pub fn skip_item(bytes: &[u8]) -> Result<usize, CborError> {
+    walk_item(bytes, false)
+}
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        let mut v = Vec::new();
-        let mut i = 0;
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallArray(byte) => {
+/// An array, map or tag that `walk_item` is inside of.
+enum OpenItem {
+    /// Holds this many more data items. Maps hold two per entry and tags one.
+    Definite(u128),
+    /// Ends with a "break". `items` have been read so far.
+    Indefinite { map: bool, items: usize },
+}
+
+/// Walks the data item at the start of `bytes` and returns its length.
+fn walk_item(bytes: &[u8], check_utf8: bool) -> Result<usize, CborError> {
+    let mut open: Vec<OpenItem> = Vec::new();
+    let mut i = 0;
+    loop {
+        let initial_byte = byte_at(bytes, i)?;
+        let start = i;
+        let head = || read_argument(&bytes[start..]).map_err(|e| e.at(start));
+        let ill_formed = |message: String| CborError::new(CborErrorKind::IllFormed(message)).at(start);
+        match expected_data_item(initial_byte) {
+            DataItem::SmallInt(_) | DataItem::SmallNegInt(_)
+            | DataItem::Bool(_) | DataItem::Null | DataItem::Undefined => i += 1,
+            DataItem::Uint1 | DataItem::Uint2 | DataItem::Uint4 | DataItem::Uint8
+            | DataItem::NegUint1 | DataItem::NegUint2 | DataItem::NegUint4 | DataItem::NegUint8 => i += head()?.1,
+            DataItem::Float2 | DataItem::Float4 | DataItem::Float8 => i += read_float(&bytes[start..]).map_err(|e| e.at(start))?.1,
+            DataItem::Tag(_) | DataItem::UnsignedBigNum | DataItem::NegativeBigNum
+            | DataItem::Tag1 | DataItem::Tag2 | DataItem::Tag4 | DataItem::Tag8 => {
+                i += head()?.1;
+                open.push(OpenItem::Definite(1));
+                continue
+            },
+            DataItem::NotSupported => match initial_byte {
+                0xf8 => match byte_at(bytes, i + 1)? {
+                    0x20.. => i += 2,
+                    simple => return Err(ill_formed(format!("Simple value {} must be encoded in a single byte", simple))),
+                },
+                _ => i += 1,
+            },
+            DataItem::SmallByteString(_) | DataItem::ByteString1 | DataItem::ByteString2 | DataItem::ByteString4 | DataItem::ByteString8
+            | DataItem::SmallTextString(_) | DataItem::TextString1 | DataItem::TextString2 | DataItem::TextString4 | DataItem::TextString8 => {
+                i += walk_string(bytes, i, check_utf8)?;
+            },
+            DataItem::TerminatedByteString | DataItem::TerminatedTextString => {
+                let major_type = initial_byte >> 5;
                 i += 1;
-                let mut count = 0;
-                while count < byte {
-                    let (t, bytes_read) = <T as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    v.push(t);
-                    i += bytes_read;
-                    count += 1;
+                while byte_at(bytes, i)? != 0xff {
+                    let chunk_byte = bytes[i];
+                    if chunk_byte >> 5 != major_type || chunk_byte & 0x1f == 0x1f {
+                        return Err(CborError::new(CborErrorKind::IllFormed(format!(
+                            "Byte {:x} is not a valid chunk of an indefinite-length string of major type {}", chunk_byte, major_type
+                        ))).at(i))
+                    }
+                    i += walk_string(bytes, i, check_utf8)?;
                 }
-            }
-            DataItem::Array8 => {
-                let data_len = u64::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
-                i += 9;
-                let mut count = 0;
-                while count < data_len {
-                    let (t, bytes_read) = <T as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    v.push(t);
-                    i += bytes_read;
-                    count += 1;
+                i += 1;
+            },
+            DataItem::SmallArray(_) | DataItem::Array1 | DataItem::Array2 | DataItem::Array4 | DataItem::Array8
+            | DataItem::SmallMap(_) | DataItem::Map1 | DataItem::Map2 | DataItem::Map4 | DataItem::Map8 => {
+                let (len, head_len) = head()?;
+                i += head_len;
+                if len > 0 {
+                    let per_entry = if initial_byte >> 5 == 5 { 2 } else { 1 };
+                    open.push(OpenItem::Definite(len as u128 * per_entry));
+                    continue
                 }
             },
-            _ => return Err(CborError::Unexpected(format!("Error from {} implementation", type_name::<T>())))
+            DataItem::TerminatedArray | DataItem::TerminatedMap => {
+                i += 1;
+                open.push(OpenItem::Indefinite { map: initial_byte == 0xbf, items: 0 });
+                continue
+            },
+            DataItem::Stop => match open.pop() {
+                Some(OpenItem::Indefinite { map: true, items }) if !items.is_multiple_of(2) => {
+                    return Err(ill_formed("Indefinite-length map ends after a key without a value".to_owned()))
+                },
+                Some(OpenItem::Indefinite { .. }) => i += 1,
+                _ => return Err(ill_formed("\"break\" outside of an indefinite length item".to_owned())),
+            },
+            DataItem::SimpleOrFloat | DataItem::InvalidByte => {
+                return Err(ill_formed(format!("Byte {:x} is not a valid initial byte", initial_byte)))
+            },
+        }
+        // A whole item has been read, which may complete the items it is in
+        loop {
+            match open.last_mut() {
+                None => return Ok(i),
+                Some(OpenItem::Definite(remaining)) => {
+                    *remaining -= 1;
+                    if *remaining > 0 {
+                        break
+                    }
+                    open.pop();
+                },
+                Some(OpenItem::Indefinite { items, .. }) => {
+                    *items += 1;
+                    break
+                },
+            }
         }
-        Ok((v, i))
     }
 }
 
-impl<T> Cbor for HashSet<T> where T: Cbor + Hash + Eq {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut v = Vec::new();
-        if self.len() < 24 {
-            v.push(0x80 + self.len() as u8);
-        } else {
-            v.push(0x9b);
-            v.extend_from_slice(&self.len().to_be_bytes());
-        }
-        for item in self {
-            v.extend_from_slice(&item.to_cbor_bytes());
+/// Checks the definite-length byte or text string that starts at `i` and returns its length.
+fn walk_string(bytes: &[u8], i: usize, check_utf8: bool) -> Result<usize, CborError> {
+    let (data_len, head_len) = read_length(&bytes[i..]).map_err(|e| e.at(i))?;
+    let content = bytes_at(bytes, i + head_len, data_len)?;
+    if check_utf8 && bytes[i] >> 5 == 3 {
+        if let Err(e) = std::str::from_utf8(content) {
+            return Err(CborError::new(CborErrorKind::InvalidUtf8).at(i + head_len + e.valid_up_to()))
         }
-        v
     }
+    Ok(head_len + data_len)
+}
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        let mut v = HashSet::new();
-        let mut i = 0;
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallArray(byte) => {
-                i += 1;
-                let mut count = 0;
-                while count < byte {
-                    let (t, bytes_read) = <T as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    v.insert(t);
-                    i += bytes_read;
-                    count += 1;
-                }
-            }
-            DataItem::Array8 => {
-                let data_len = u64::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
-                i += 9;
-                let mut count = 0;
-                while count < data_len {
-                    let (t, bytes_read) = <T as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    v.insert(t);
-                    i += bytes_read;
-                    count += 1;
-                }
-            },
-            _ => return Err(CborError::Unexpected(format!("Error from {} implementation", type_name::<T>())))
-        }
-        Ok((v, i))
+/// Checks that `bytes` holds exactly one data item in core deterministic encoding
+/// (RFC 8949 section 4.2.1): shortest heads and floats, no indefinite lengths and
+/// map keys in strictly ascending bytewise order.
+pub fn check_deterministic(bytes: &[u8]) -> Result<(), CborError> {
+    let bytes_read = check_deterministic_item(bytes)?;
+    if bytes_read < bytes.len() {
+        return Err(CborError::new(CborErrorKind::TrailingBytes(bytes.len() - bytes_read)).at(bytes_read))
     }
+    Ok(())
 }
 
-impl<T> Cbor for BTreeSet<T> where T: Cbor + Hash + Eq + Ord {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut v = Vec::new();
-        if self.len() < 24 {
-            v.push(0x80 + self.len() as u8);
-        } else {
-            v.push(0x9b);
-            v.extend_from_slice(&self.len().to_be_bytes());
-        }
-        for item in self {
-            v.extend_from_slice(&item.to_cbor_bytes());
+/// Whether `bytes` holds exactly one deterministically encoded data item.
+pub fn is_deterministic(bytes: &[u8]) -> bool {
+    check_deterministic(bytes).is_ok()
+}
+
+/// An array, map or tag that `check_deterministic` is inside of.
+enum OpenDeterministic {
+    /// `index` is the item being checked.
+    Array { len: u64, index: usize },
+    /// `items` counts keys and values checked so far. `key` is the key of the current entry.
+    Map { len: u64, items: u64, key: Range<usize>, previous_key: Option<Range<usize>> },
+    Tag,
+}
+
+impl OpenDeterministic {
+    /// Where an error inside the item being checked is, as seen from this container.
+    fn path_segment(&self, bytes: &[u8]) -> Option<PathSegment> {
+        match self {
+            OpenDeterministic::Array { index, .. } => Some(PathSegment::Index(*index)),
+            OpenDeterministic::Map { items, .. } if items.is_multiple_of(2) => Some(PathSegment::Index((items / 2) as usize)),
+            OpenDeterministic::Map { key, .. } => Some(PathSegment::Key(describe_key(&bytes[key.clone()]))),
+            OpenDeterministic::Tag => None,
         }
-        v
     }
+}
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        let mut v = BTreeSet::new();
-        let mut i = 0;
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallArray(byte) => {
-                i += 1;
-                let mut count = 0;
-                while count < byte {
-                    let (t, bytes_read) = <T as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    v.insert(t);
-                    i += bytes_read;
-                    count += 1;
-                }
-            }
-            DataItem::Array8 => {
-                let data_len = u64::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
-                i += 9;
-                let mut count = 0;
-                while count < data_len {
-                    let (t, bytes_read) = <T as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    v.insert(t);
-                    i += bytes_read;
-                    count += 1;
-                }
-            },
-            _ => return Err(CborError::Unexpected(format!("Error from {} implementation", type_name::<T>())))
-        }
-        Ok((v, i))
+/// Checks one data item for `check_deterministic` and returns its size.
+/// Errors get the path of the arrays and maps they are in.
+fn check_deterministic_item(bytes: &[u8]) -> Result<usize, CborError> {
+    let mut open = Vec::new();
+    match check_deterministic_items(bytes, &mut open) {
+        Ok(bytes_read) => Ok(bytes_read),
+        Err(mut e) => {
+            let mut path: Vec<PathSegment> = open.iter().filter_map(|item| item.path_segment(bytes)).collect();
+            path.append(&mut e.path);
+            e.path = path;
+            Err(e)
+        },
     }
 }
 
-impl<K, V> Cbor for HashMap<K, V> 
-where 
-    K: Cbor + Hash + Eq,
-    V: Cbor 
-{
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        if self.len() < 24 {
-            bytes.push(0xa0 + self.len() as u8);
-            for (key, value) in self {
-                bytes.extend_from_slice(&key.to_cbor_bytes());
-                bytes.extend_from_slice(&value.to_cbor_bytes());
-            }
-        } else {
-            bytes.push(0xbb);
-            bytes.extend_from_slice(&self.len().to_be_bytes());
-            for (key, value) in self {
-                bytes.extend_from_slice(&key.to_cbor_bytes());
-                bytes.extend_from_slice(&value.to_cbor_bytes());
+/// The loop of `check_deterministic_item`. Errors are at their offset in `bytes`, and leave
+/// the items they are inside of in `open`.
+fn check_deterministic_items(bytes: &[u8], open: &mut Vec<OpenDeterministic>) -> Result<usize, CborError> {
+    let mut i = 0;
+    loop {
+        let start = i;
+        if let Some(OpenDeterministic::Map { items, key, .. }) = open.last_mut() {
+            if items.is_multiple_of(2) {
+                *key = start..start;
             }
         }
-        bytes
-    }
-
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        // println!("bytes: {:x?}", bytes);
-        let mut map = HashMap::new();
-        let mut i = 0;
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallMap(byte) => {
-                i += 1;
-                let mut count = 0;
-                while count < byte {
-                    let (key, key_bytes_read) = <K as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    let (value, value_bytes_read) = <V as Cbor>::from_cbor_bytes(&bytes[i+key_bytes_read..])?;
-                    map.insert(key, value);
-                    i += key_bytes_read + value_bytes_read;
-                    count += 1;
+        let initial_byte = byte_at(bytes, start)?;
+        let item = &bytes[start..];
+        let major_type = initial_byte >> 5;
+        let error = |kind: CborErrorKind| CborError::new(kind).at(start);
+        match (major_type, initial_byte & 0x1f) {
+            (_, 0x1c..0x1f) => return Err(error(CborErrorKind::IllFormed(format!("Reserved initial byte {:x}", initial_byte)))),
+            (2..=5, 0x1f) => return Err(error(CborErrorKind::NotDeterministic(format!("indefinite length {}", major_type_name(initial_byte))))),
+            (7, 0x1f) => return Err(error(CborErrorKind::IllFormed("\"break\" outside of an indefinite length item".to_owned()))),
+            (_, 0x1f) => return Err(error(CborErrorKind::IllFormed(format!("Byte {:x} is not followed by a definite argument", initial_byte)))),
+            (7, info) => i += check_deterministic_simple(item, info).map_err(|e| e.at(start))?,
+            _ => {
+                let (argument, head_len) = read_argument(item).map_err(|e| e.at(start))?;
+                let mut shortest = Vec::new();
+                write_head(&mut shortest, major_type, argument);
+                if shortest.len() != head_len {
+                    return Err(error(CborErrorKind::NotDeterministic(format!("{} head is not in its shortest form", major_type_name(initial_byte)))))
                 }
-            }
-            DataItem::Map8 => {
-                let data_len = u64::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
-                i += 9;
-                let mut count = 0;
-                while count < data_len {
-                    let (key, key_bytes_read) = <K as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    let (value, value_bytes_read) = <V as Cbor>::from_cbor_bytes(&bytes[i+key_bytes_read..])?;
-                    map.insert(key, value);
-                    i += key_bytes_read + value_bytes_read;
-                    count += 1;
+                i += head_len;
+                match major_type {
+                    2 | 3 => {
+                        let (len, _) = read_length(item).map_err(|e| e.at(start))?;
+                        let content = bytes_at(item, head_len, len).map_err(|e| e.at(start))?;
+                        if major_type == 3 && std::str::from_utf8(content).is_err() {
+                            return Err(CborError::new(CborErrorKind::InvalidUtf8).at(i))
+                        }
+                        i += len;
+                    },
+                    4 if argument > 0 => {
+                        open.push(OpenDeterministic::Array { len: argument, index: 0 });
+                        continue
+                    },
+                    5 if argument > 0 => {
+                        open.push(OpenDeterministic::Map { len: argument, items: 0, key: i..i, previous_key: None });
+                        continue
+                    },
+                    6 => {
+                        open.push(OpenDeterministic::Tag);
+                        continue
+                    },
+                    _ => {},
                 }
             },
-            _ => return Err(CborError::Unexpected(format!("Error from HashMap<{}, {}> implementation", type_name::<K>(), type_name::<V>())))
         }
-        Ok((map, i))
-    }
-}
-
-
-impl<K, V> Cbor for BTreeMap<K, V> 
-where 
-    K: Cbor + Hash + Eq + Ord,
-    V: Cbor 
-{
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
-        if self.len() < 24 {
-            bytes.push(0xa0 + self.len() as u8);
-            for (key, value) in self {
-                bytes.extend_from_slice(&key.to_cbor_bytes());
-                bytes.extend_from_slice(&value.to_cbor_bytes());
-            }
-        } else {
-            bytes.push(0xbb);
-            bytes.extend_from_slice(&self.len().to_be_bytes());
-            for (key, value) in self {
-                bytes.extend_from_slice(&key.to_cbor_bytes());
-                bytes.extend_from_slice(&value.to_cbor_bytes());
+        // A whole item has been checked, which may complete the items it is in
+        loop {
+            match open.last_mut() {
+                None => return Ok(i),
+                Some(OpenDeterministic::Tag) => {
+                    open.pop();
+                },
+                Some(OpenDeterministic::Array { len, index }) => {
+                    *index += 1;
+                    if *index as u64 != *len {
+                        break
+                    }
+                    open.pop();
+                },
+                Some(OpenDeterministic::Map { len, items, key, previous_key }) => {
+                    *items += 1;
+                    if !items.is_multiple_of(2) {
+                        key.end = i;
+                        if previous_key.as_ref().is_some_and(|previous| bytes[previous.clone()] >= bytes[key.clone()]) {
+                            return Err(CborError::new(CborErrorKind::NotDeterministic("map keys are not in ascending order".to_owned())).at(key.start))
+                        }
+                        break
+                    }
+                    *previous_key = Some(key.clone());
+                    if *items / 2 != *len {
+                        break
+                    }
+                    open.pop();
+                },
             }
         }
-        bytes
     }
+}
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
-        where 
-            Self: Sized 
-    {
-        // println!("bytes: {:x?}", bytes);
-        let mut map = BTreeMap::new();
-        let mut i = 0;
-        match expected_data_item(bytes[0]) {
-            DataItem::SmallMap(byte) => {
-                i += 1;
-                let mut count = 0;
-                while count < byte {
-                    let (key, key_bytes_read) = <K as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    let (value, value_bytes_read) = <V as Cbor>::from_cbor_bytes(&bytes[i+key_bytes_read..])?;
-                    map.insert(key, value);
-                    i += key_bytes_read + value_bytes_read;
-                    count += 1;
-                }
-            }
-            DataItem::Map8 => {
-                let data_len = u64::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
-                i += 9;
-                let mut count = 0;
-                while count < data_len {
-                    let (key, key_bytes_read) = <K as Cbor>::from_cbor_bytes(&bytes[i..])?;
-                    let (value, value_bytes_read) = <V as Cbor>::from_cbor_bytes(&bytes[i+key_bytes_read..])?;
-                    map.insert(key, value);
-                    i += key_bytes_read + value_bytes_read;
-                    count += 1;
-                }
-            },
-            _ => return Err(CborError::Unexpected(format!("Error from BTreeMap<{}, {}> implementation", type_name::<K>(), type_name::<V>())))
-        }
-        Ok((map, i))
+/// Checks a simple value or float for `check_deterministic`. A float must not fit a narrower
+/// width, which for NaN means its payload would be cut off.
+fn check_deterministic_simple(bytes: &[u8], info: u8) -> Result<usize, CborError> {
+    let fits_narrower = match info {
+        0x00..0x18 => return Ok(1),
+        0x18 => match byte_at(bytes, 1)? {
+            0x00..0x20 => return Err(CborErrorKind::IllFormed(format!("Simple value {} in two bytes", bytes[1])).into()),
+            _ => return Ok(2),
+        },
+        0x19 => return array_at::<2>(bytes, 1).map(|_| 3),
+        0x1a => {
+            let bits = u32::from_be_bytes(array_at(bytes, 1)?);
+            let float = f32::from_bits(bits);
+            if float.is_nan() { bits & 0x1fff == 0 } else { f64_to_f16(float as f64).is_some() }
+        },
+        _ => {
+            let bits = u64::from_be_bytes(array_at(bytes, 1)?);
+            let float = f64::from_bits(bits);
+            if float.is_nan() { bits & 0x1fff_ffff == 0 } else { (float as f32) as f64 == float }
+        },
+    };
+    if fits_narrower {
+        return Err(CborErrorKind::NotDeterministic("float is not in its shortest form".to_owned()).into())
     }
+    Ok(if info == 0x1a { 5 } else { 9 })
 }
 
 ///This is a sample impl for an enum.
@@ -836,46 +2072,42 @@ pub enum Item {
 }
 
 impl Cbor for Item {
-    fn to_cbor_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+    fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
         match self {
             Item::Int(item) => {
                 bytes.push(0xc6);
-                bytes.extend_from_slice(&item.to_cbor_bytes());
+                item.encode_into(bytes, options);
             },
             Item::Float(item) => {
                 bytes.push(0xc6+1);
-                bytes.extend_from_slice(&item.to_cbor_bytes());
+                item.encode_into(bytes, options);
             },
             Item::String(item) => {
                 bytes.push(0xc6+2);
-                bytes.extend_from_slice(&item.to_cbor_bytes());
+                item.encode_into(bytes, options);
             },
         };
-        bytes
     }
 
-    fn from_cbor_bytes(bytes: &[u8]) -> Result<(Self, usize), CborError>
+    fn from_cbor_bytes_with(bytes: &[u8], limits: &mut Limits) -> Result<(Self, usize), CborError>
         where 
             Self: Sized 
     {
-        match expected_data_item(bytes[0]) {
-            DataItem::Tag(byte) => match byte {
-                0 => {
-                    let (item, bytes_read) = <Vec<i32> as Cbor>::from_cbor_bytes(&bytes[1..])?;
-                    Ok((Self::Int(item), bytes_read+1)) // The +1 is to account for the Tag
-                },
-                1 => {
-                    let (item, bytes_read) = <Vec<f32> as Cbor>::from_cbor_bytes(&bytes[1..])?;
-                    Ok((Self::Float(item), bytes_read+1)) // The +1 is to account for the Tag
-                },
-                2 => {
-                    let (item, bytes_read) = <Vec<String> as Cbor>::from_cbor_bytes(&bytes[1..])?;
-                    Ok((Self::String(item), bytes_read+1)) // The +1 is to account for the Tag
-                },
-                _ => Err(CborError::Unexpected(format!("Error from Item implementation. Expected either 0x0, 0x1, or 0x2. Got {:x}", byte)))
+        let (tag, head_len) = read_tag(bytes)?;
+        match tag {
+            6 => {
+                let (item, bytes_read) = limits.nested(|limits| <Vec<i32> as Cbor>::from_cbor_bytes_with(&bytes[head_len..], limits)).map_err(|e| e.at(head_len))?;
+                Ok((Self::Int(item), bytes_read+head_len)) // The +head_len is to account for the Tag
             },
-            _ => Err(CborError::Unexpected(format!("Error from Item implementation."))),
+            7 => {
+                let (item, bytes_read) = limits.nested(|limits| <Vec<f32> as Cbor>::from_cbor_bytes_with(&bytes[head_len..], limits)).map_err(|e| e.at(head_len))?;
+                Ok((Self::Float(item), bytes_read+head_len)) // The +head_len is to account for the Tag
+            },
+            8 => {
+                let (item, bytes_read) = limits.nested(|limits| <Vec<String> as Cbor>::from_cbor_bytes_with(&bytes[head_len..], limits)).map_err(|e| e.at(head_len))?;
+                Ok((Self::String(item), bytes_read+head_len)) // The +head_len is to account for the Tag
+            },
+            _ => Err(CborErrorKind::UnknownVariant(format!("{} for Item. Expected either 6, 7, or 8", tag)).into())
         }
     }
 }
@@ -891,7 +2123,7 @@ pub fn expected_data_item(byte: u8) -> DataItem {
         0x19        => DataItem::Uint2,                           //unsigned integer (two-byte uint16_t follows),
         0x1a        => DataItem::Uint4,                           //unsigned integer (four-byte uint32_t follows),
         0x1b        => DataItem::Uint8,                           //unsigned integer (eight-byte uint64_t follows),
-        0x20..0x38  => DataItem::SmallNegInt(0x20 - byte as i8),    //negative integer -1-0x00..-1-0x17 (-1..-24),
+        0x20..0x38  => DataItem::SmallNegInt(-1 - (byte - 0x20) as i8),    //negative integer -1-0x00..-1-0x17 (-1..-24),
         0x38        => DataItem::NegUint1,                        //negative integer -1-n (one-byte uint8_t for n follows),
         0x39        => DataItem::NegUint2,                        //negative integer -1-n (two-byte uint16_t for n follows),
         0x3a        => DataItem::NegUint4,                        //negative integer -1-n (four-byte uint32_t for n follows),
@@ -920,15 +2152,18 @@ pub fn expected_data_item(byte: u8) -> DataItem {
         0xba        => DataItem::Map4,      //map (four-byte uint32_t for n, and then n pairs of data items follow),
         0xbb        => DataItem::Map8,      //map (eight-byte uint64_t for n, and then n pairs of data items follow),
         0xbf        => DataItem::TerminatedMap,      //map, pairs of data items follow, terminated by "break",
-        0xc0        => DataItem::NotSupported,      //text-based date/time (data item follows; see Section 3.4.1),
-        0xc1        => DataItem::NotSupported,      //epoch-based date/time (data item follows; see Section 3.4.2),
+        0xc0        => DataItem::Tag(0),      //text-based date/time (data item follows; see Section 3.4.1),
+        0xc1        => DataItem::Tag(1),      //epoch-based date/time (data item follows; see Section 3.4.2),
         0xc2        => DataItem::UnsignedBigNum,      //unsigned bignum (data item "byte string" follows),
         0xc3        => DataItem::NegativeBigNum,      //negative bignum (data item "byte string" follows),
-        0xc4        => DataItem::NotSupported,      //decimal Fraction (data item "array" follows; see Section 3.4.4),
-        0xc5        => DataItem::NotSupported,      //bigfloat (data item "array" follows; see Section 3.4.4),
-        0xc6..0xd5  => DataItem::Tag(byte - 0xc6),      //(tag),
-        0xd5..0xd8  => DataItem::NotSupported,      //expected conversion (data item follows; see Section 3.4.5.2),
-        0xd8..0xdb  => DataItem::NotSupported,      //(more tags; 1/2/4/8 bytes of tag number and then a data item follow),
+        0xc4        => DataItem::Tag(4),      //decimal Fraction (data item "array" follows; see Section 3.4.4),
+        0xc5        => DataItem::Tag(5),      //bigfloat (data item "array" follows; see Section 3.4.4),
+        0xc6..0xd5  => DataItem::Tag(byte - 0xc0),      //(tag),
+        0xd5..0xd8  => DataItem::Tag(byte - 0xc0),      //expected conversion (data item follows; see Section 3.4.5.2),
+        0xd8        => DataItem::Tag1,      //(more tags; 1 byte of tag number and then a data item follow),
+        0xd9        => DataItem::Tag2,      //(more tags; 2 bytes of tag number and then a data item follow),
+        0xda        => DataItem::Tag4,      //(more tags; 4 bytes of tag number and then a data item follow),
+        0xdb        => DataItem::Tag8,      //(more tags; 8 bytes of tag number and then a data item follow),
         0xe0..0xf4  => DataItem::NotSupported,      //(simple value),
         0xf4        => DataItem::Bool(false),      //false,
         0xf5        => DataItem::Bool(true),      //true,
@@ -981,6 +2216,543 @@ mod tests {
         assert_eq!(long_slice, decoded_long_slice);
     }
 
+    #[test]
+    fn test_every_length_width() {
+        let text = "a".repeat(300);
+        let mut encoded = vec![0x79, 0x01, 0x2c];
+        encoded.extend_from_slice(text.as_bytes());
+        assert_eq!(decode_cbor::<String>(&encoded).unwrap(), text);
+        assert_eq!(text.to_cbor_bytes()[..3], [0x79, 0x01, 0x2c]);
+
+        let encoded = [0x7a, 0, 0, 0, 2, b'h', b'i'];
+        assert_eq!(decode_cbor::<String>(&encoded).unwrap(), "hi");
+
+        let encoded = [0x58, 0x02, 0xaa, 0xbb];
+        assert_eq!(byteslice_from_cbor(&encoded).unwrap(), (vec![0xaa, 0xbb], 4));
+        let encoded = [0x5a, 0, 0, 0, 1, 0xaa];
+        assert_eq!(byteslice_from_cbor(&encoded).unwrap(), (vec![0xaa], 6));
+
+        let mut encoded = vec![0x98, 25];
+        encoded.extend([7u8; 25]);
+        let expected = vec![7u8; 25];
+        assert_eq!(decode_cbor::<Vec<u8>>(&encoded).unwrap(), expected);
+        assert_eq!(expected.to_cbor_bytes(), encoded);
+        assert_eq!(decode_cbor::<HashSet<u8>>(&[0x99, 0, 2, 1, 2]).unwrap(), HashSet::from([1, 2]));
+        assert_eq!(decode_cbor::<BTreeSet<u8>>(&[0x9a, 0, 0, 0, 2, 1, 2]).unwrap(), BTreeSet::from([1, 2]));
+
+        let encoded = [0xb8, 0x01, 0x01, 0x61, b'a'];
+        assert_eq!(decode_cbor::<HashMap<u8, String>>(&encoded).unwrap(), HashMap::from([(1, "a".to_owned())]));
+        let encoded = [0xb9, 0x00, 0x01, 0x01, 0x61, b'a'];
+        assert_eq!(decode_cbor::<BTreeMap<u8, String>>(&encoded).unwrap(), BTreeMap::from([(1, "a".to_owned())]));
+        let encoded = [0xba, 0, 0, 0, 1, 0x01, 0x61, b'a'];
+        assert_eq!(decode_cbor::<HashMap<u8, String>>(&encoded).unwrap(), HashMap::from([(1, "a".to_owned())]));
+    }
+
+    #[test]
+    fn test_indefinite_length() {
+        let encoded = [0x5f, 0x42, 0x01, 0x02, 0x43, 0x03, 0x04, 0x05, 0xff];
+        assert_eq!(byteslice_from_cbor(&encoded).unwrap(), (vec![1, 2, 3, 4, 5], 9));
+        let encoded = [0x7f, 0x65, b's', b't', b'r', b'e', b'a', 0x64, b'm', b'i', b'n', b'g', 0xff];
+        assert_eq!(decode_cbor::<String>(&encoded).unwrap(), "streaming");
+        assert_eq!(decode_cbor::<String>(&[0x7f, 0xff]).unwrap(), "");
+
+        assert_eq!(decode_cbor::<Vec<i32>>(&[0x9f, 0xff]).unwrap(), Vec::<i32>::new());
+        let encoded = [0x9f, 0x82, 0x02, 0x03, 0x9f, 0x04, 0x05, 0xff, 0xff];
+        assert_eq!(decode_cbor::<Vec<Vec<u8>>>(&encoded).unwrap(), vec![vec![2, 3], vec![4, 5]]);
+        let encoded = [0x83, 0x01, 0x9f, 0x02, 0xff, 0x82, 0x04, 0x05];
+        assert_eq!(<Vec<u8> as Cbor>::from_cbor_bytes(&encoded[2..]).unwrap().1, 3);
+
+        let encoded = [0xbf, 0x61, b'a', 0x01, 0x61, b'b', 0x22, 0xff];
+        let map: BTreeMap<String, i32> = decode_cbor(&encoded).unwrap();
+        assert_eq!(map, BTreeMap::from([("a".to_owned(), 1), ("b".to_owned(), -3)]));
+        let (_, bytes_read) = <HashMap<String, i32> as Cbor>::from_cbor_bytes(&encoded).unwrap();
+        assert_eq!(bytes_read, encoded.len());
+
+        // Chunks of the wrong major type and nested indefinite chunks are rejected
+        assert!(byteslice_from_cbor(&[0x5f, 0x61, b'a', 0xff]).is_err());
+        assert!(decode_cbor::<String>(&[0x7f, 0x41, b'a', 0xff]).is_err());
+        assert!(decode_cbor::<String>(&[0x7f, 0x7f, 0xff, 0xff]).is_err());
+        // A multi-byte character split across chunks is rejected
+        assert!(decode_cbor::<String>(&[0x7f, 0x61, 0xc3, 0x61, 0xa9, 0xff]).is_err());
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let mut map = BTreeMap::new();
+        map.insert("key".to_owned(), vec![-1000i64, 5, i64::MAX]);
+        map.insert("other".repeat(10), vec![]);
+        let bytes = map.to_cbor_bytes();
+        for len in 0..bytes.len() {
+            match decode_cbor::<BTreeMap<String, Vec<i64>>>(&bytes[..len]) {
+                Err(CborError { kind: CborErrorKind::UnexpectedEnd { needed }, offset, .. }) => {
+                    assert!(needed > 0);
+                    assert_eq!(offset, len);
+                },
+                other => panic!("Expected UnexpectedEnd for {} bytes. Got {:?}", len, other),
+            }
+        }
+        assert!(matches!(decode_cbor::<u32>(&[]), Err(CborError { kind: CborErrorKind::UnexpectedEnd { needed: 1 }, .. })));
+        assert!(matches!(decode_cbor::<u32>(&[0x1a, 0x00]), Err(CborError { kind: CborErrorKind::UnexpectedEnd { needed: 3 }, .. })));
+        assert!(matches!(decode_cbor::<f64>(&[0xfb]), Err(CborError { kind: CborErrorKind::UnexpectedEnd { needed: 8 }, .. })));
+        assert!(matches!(decode_cbor::<String>(&[0x63, b'a']), Err(CborError { kind: CborErrorKind::UnexpectedEnd { needed: 2 }, .. })));
+
+        // Huge declared lengths are errors, not panics or allocations
+        let huge = [0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(byteslice_from_cbor(&huge).is_err());
+        assert!(decode_cbor::<String>(&[0x7b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode_cbor::<Vec<u8>>(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode_cbor::<HashMap<u8, u8>>(&[0xbb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode_cbor::<Vec<u8>>(&[0x9f, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_error_offset_and_path() {
+        let mut first = HashMap::new();
+        first.insert("a".to_owned(), 1u16);
+        let mut second = HashMap::new();
+        second.insert("key".to_owned(), 1000u16);
+        let bytes = vec![first, second].to_cbor_bytes();
+        let error = decode_cbor::<Vec<HashMap<String, u8>>>(&bytes).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::IntegerOverflow { value: 1000, target: "u8" });
+        assert_eq!(error.offset, 10);
+        assert_eq!(error.path, vec![PathSegment::Index(1), PathSegment::Key("key".to_owned())]);
+        assert_eq!(error.to_string(), "1000 does not fit in u8 at byte 10 in [1].key");
+
+        let error = decode_cbor::<Vec<String>>(&[0x82, 0x61, b'a', 0x01]).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::TypeMismatch { expected: "text string", found: 0x01 });
+        assert_eq!(error.to_string(), "expected text string, found unsigned integer (0x01) at byte 3 in [1]");
+
+        let error = decode_cbor::<String>(&[0x63, b'a', 0xff, b'b']).unwrap_err();
+        assert_eq!((error.kind, error.offset), (CborErrorKind::InvalidUtf8, 2));
+
+        let error: Box<dyn std::error::Error> = Box::new(decode_cbor::<u8>(&[]).unwrap_err());
+        assert_eq!(error.to_string(), "unexpected end of input, 1 more byte(s) needed at byte 0");
+    }
+
+    #[test]
+    fn test_floats() {
+        let shortest = EncodeOptions { float_encoding: FloatEncoding::Shortest, ..Default::default() };
+        let vectors: [(f64, &[u8]); 16] = [
+            (0.0, &[0xf9, 0x00, 0x00]),
+            (-0.0, &[0xf9, 0x80, 0x00]),
+            (1.0, &[0xf9, 0x3c, 0x00]),
+            (1.1, &[0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]),
+            (1.5, &[0xf9, 0x3e, 0x00]),
+            (65504.0, &[0xf9, 0x7b, 0xff]),
+            (100000.0, &[0xfa, 0x47, 0xc3, 0x50, 0x00]),
+            (3.4028234663852886e+38, &[0xfa, 0x7f, 0x7f, 0xff, 0xff]),
+            (1.0e+300, &[0xfb, 0x7e, 0x37, 0xe4, 0x3c, 0x88, 0x00, 0x75, 0x9c]),
+            (5.960464477539063e-8, &[0xf9, 0x00, 0x01]),
+            (0.00006103515625, &[0xf9, 0x04, 0x00]),
+            (-4.0, &[0xf9, 0xc4, 0x00]),
+            (-4.1, &[0xfb, 0xc0, 0x10, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66]),
+            (f64::INFINITY, &[0xf9, 0x7c, 0x00]),
+            (f64::NAN, &[0xf9, 0x7e, 0x00]),
+            (f64::NEG_INFINITY, &[0xf9, 0xfc, 0x00]),
+        ];
+        for (float, expected) in vectors {
+            let bytes = float.to_cbor_bytes_with(&shortest);
+            assert_eq!(bytes, expected, "{}", float);
+            assert_eq!(Value::Float(float, FloatWidth::Double).to_cbor_bytes_with(&shortest), expected);
+            let decoded: f64 = decode_cbor(&bytes).unwrap();
+            assert!(decoded == float || (decoded.is_nan() && float.is_nan()));
+            assert_eq!(decoded.to_bits() >> 63, float.to_bits() >> 63);
+        }
+        assert_eq!(1.5f32.to_cbor_bytes_with(&shortest), vec![0xf9, 0x3e, 0x00]);
+        assert_eq!(1.5f32.to_cbor_bytes(), vec![0xfa, 0x3f, 0xc0, 0x00, 0x00]);
+        assert_eq!(vec![1.0f64, 0.5].to_cbor_bytes_with(&shortest), vec![0x82, 0xf9, 0x3c, 0x00, 0xf9, 0x38, 0x00]);
+
+        // Every width decodes into both f32 and f64
+        assert_eq!(decode_cbor::<f32>(&[0xf9, 0x3e, 0x00]).unwrap(), 1.5);
+        assert_eq!(decode_cbor::<f32>(&[0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]).unwrap(), 1.5);
+        assert_eq!(decode_cbor::<f64>(&[0xfa, 0x47, 0xc3, 0x50, 0x00]).unwrap(), 100000.0);
+        assert!(decode_cbor::<f32>(&[0xf9, 0x7e, 0x00]).unwrap().is_nan());
+        assert!(decode_cbor::<f32>(&[0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]).is_err());
+
+        // Every half precision value survives a round trip through f64
+        for half in 0..=u16::MAX {
+            let float = f16_to_f64(half);
+            if !float.is_nan() {
+                assert_eq!(f64_to_f16(float), Some(half));
+            }
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let options = EncodeOptions::deterministic();
+        let mut map = HashMap::new();
+        for key in ["bb", "a", "c", "aa", "b"] {
+            map.insert(key.to_owned(), 1.5f64);
+        }
+        let bytes = map.to_cbor_bytes_with(&options);
+        assert_eq!(bytes, vec![
+            0xa5,
+            0x61, b'a', 0xf9, 0x3e, 0x00,
+            0x61, b'b', 0xf9, 0x3e, 0x00,
+            0x61, b'c', 0xf9, 0x3e, 0x00,
+            0x62, b'a', b'a', 0xf9, 0x3e, 0x00,
+            0x62, b'b', b'b', 0xf9, 0x3e, 0x00,
+        ]);
+        assert!(is_deterministic(&bytes));
+
+        // Sorting is by encoded key, so 10 comes before -1 and 1000
+        let map: BTreeMap<i32, bool> = [(-1, true), (10, false), (1000, true)].into_iter().collect();
+        assert_eq!(map.to_cbor_bytes_with(&options), vec![0xa3, 0x0a, 0xf4, 0x19, 0x03, 0xe8, 0xf5, 0x20, 0xf5]);
+        assert!(!is_deterministic(&map.to_cbor_bytes()));
+
+        let set: HashSet<u16> = [300, 2, 1].into_iter().collect();
+        assert_eq!(set.to_cbor_bytes_with(&options), vec![0x83, 0x01, 0x02, 0x19, 0x01, 0x2c]);
+
+        let value = Value::Map(vec![
+            (Value::Text("z".to_owned()), Value::Unsigned(0)),
+            (Value::Unsigned(1), Value::Map(vec![
+                (Value::Negative(0), Value::Null),
+                (Value::Unsigned(0), Value::Null),
+            ])),
+        ]);
+        let bytes = value.to_cbor_bytes_with(&options);
+        assert_eq!(bytes, vec![0xa2, 0x01, 0xa2, 0x00, 0xf6, 0x20, 0xf6, 0x61, b'z', 0x00]);
+        assert_eq!(check_deterministic(&bytes), Ok(()));
+        assert!(!is_deterministic(&value.to_cbor_bytes()));
+    }
+
+    #[test]
+    fn test_check_deterministic() {
+        let not_deterministic = |bytes: &[u8]| matches!(
+            check_deterministic(bytes).map_err(|e| e.kind),
+            Err(CborErrorKind::NotDeterministic(_))
+        );
+        assert!(is_deterministic(&[0x17]));
+        assert!(not_deterministic(&[0x18, 0x17]));
+        assert!(not_deterministic(&[0x39, 0x00, 0x10]));
+        assert!(not_deterministic(&[0xd8, 0x01, 0x00]));
+        assert!(not_deterministic(&[0x9f, 0xff]));
+        assert!(not_deterministic(&[0x5f, 0x41, 0x00, 0xff]));
+        assert!(not_deterministic(&[0xfa, 0x3f, 0xc0, 0x00, 0x00]));
+        assert!(not_deterministic(&[0xfa, 0x7f, 0xc0, 0x00, 0x00]));
+        assert!(is_deterministic(&[0xfa, 0x47, 0xc3, 0x50, 0x00]));
+        assert!(is_deterministic(&[0xf9, 0x7e, 0x00]));
+        assert!(not_deterministic(&[0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]));
+
+        // Duplicate and unordered keys, reported at the second key
+        let error = check_deterministic(&[0x81, 0xa2, 0x61, b'b', 0x00, 0x61, b'a', 0x00]).unwrap_err();
+        assert!(matches!(error.kind, CborErrorKind::NotDeterministic(_)));
+        assert_eq!(error.offset, 5);
+        assert_eq!(error.path, vec![PathSegment::Index(0), PathSegment::Key("a".to_owned())]);
+        assert!(not_deterministic(&[0xa2, 0x00, 0x00, 0x00, 0x00]));
+
+        assert_eq!(check_deterministic(&[0x00, 0x00]).unwrap_err().kind, CborErrorKind::TrailingBytes(1));
+        assert_eq!(check_deterministic(&[0x82, 0x00]).unwrap_err().kind, CborErrorKind::UnexpectedEnd { needed: 1 });
+        assert!(matches!(check_deterministic(&[0xff]).unwrap_err().kind, CborErrorKind::IllFormed(_)));
+        assert!(matches!(check_deterministic(&[0xf8, 0x10]).unwrap_err().kind, CborErrorKind::IllFormed(_)));
+
+        // Errors in keys are at the entry's index, errors in values at its key
+        let error = check_deterministic(&[0xa2, 0x00, 0x80, 0xc1, 0x18, 0x01, 0x00]).unwrap_err();
+        assert_eq!((error.offset, error.path), (4, vec![PathSegment::Index(1)]));
+        let error = check_deterministic(&[0xa1, 0x61, b'k', 0x81, 0x62, 0xff, 0xff]).unwrap_err();
+        assert_eq!((error.kind, error.offset), (CborErrorKind::InvalidUtf8, 5));
+        assert_eq!(error.path, vec![PathSegment::Key("k".to_owned()), PathSegment::Index(0)]);
+
+        // A hundred thousand nested arrays, then tags
+        let mut nested = vec![0x81; 100_000];
+        nested.push(0x00);
+        assert_eq!(check_deterministic(&nested), Ok(()));
+        let mut tags = vec![0xc6; 100_000];
+        tags.push(0x18);
+        assert_eq!(check_deterministic(&tags).unwrap_err().kind, CborErrorKind::UnexpectedEnd { needed: 1 });
+    }
+
+    #[test]
+    fn test_encode_into() {
+        // Implementations that only provide encode_into get the other encoding methods
+        struct Minimal;
+        impl Cbor for Minimal {
+            fn encode_into(&self, bytes: &mut Vec<u8>, options: &EncodeOptions) {
+                bytes.push(if options.deterministic { 0xf5 } else { 0xf6 });
+            }
+
+            fn from_cbor_bytes_with(_bytes: &[u8], _limits: &mut Limits) -> Result<(Self, usize), CborError> {
+                Ok((Minimal, 1))
+            }
+        }
+        assert_eq!(vec![Minimal, Minimal].to_cbor_bytes(), vec![0x82, 0xf6, 0xf6]);
+        assert_eq!(Minimal.to_cbor_bytes_with(&EncodeOptions::deterministic()), vec![0xf5]);
+
+        // Implementations that only provide to_cbor_bytes and from_cbor_bytes still nest
+        #[derive(Debug, PartialEq)]
+        struct Legacy;
+        impl Cbor for Legacy {
+            fn to_cbor_bytes(&self) -> Vec<u8> {
+                vec![0xf6]
+            }
+
+            fn from_cbor_bytes(_bytes: &[u8]) -> Result<(Self, usize), CborError> {
+                Ok((Legacy, 1))
+            }
+        }
+        assert_eq!(vec![Legacy, Legacy].to_cbor_bytes_with(&EncodeOptions::deterministic()), vec![0x82, 0xf6, 0xf6]);
+        assert_eq!(decode_cbor_with::<Vec<Legacy>>(&[0x82, 0xf6, 0xf6], &DecodeOptions::default()).unwrap(), vec![Legacy, Legacy]);
+
+        let mut bytes = vec![0x83];
+        let options = EncodeOptions::default();
+        1u8.encode_into(&mut bytes, &options);
+        "a".to_owned().encode_into(&mut bytes, &options);
+        Tagged::new(1, -1i64).encode_into(&mut bytes, &options);
+        assert_eq!(bytes, vec![0x83, 0x01, 0x61, b'a', 0xc1, 0x20]);
+        assert_eq!(decode_cbor::<Value>(&bytes).unwrap(), Value::Array(vec![
+            Value::Unsigned(1),
+            Value::Text("a".to_owned()),
+            Value::Tag(1, Box::new(Value::Negative(0))),
+        ]));
+    }
+
+    #[test]
+    fn test_decode_exact_and_prefix() {
+        let bytes = [0x82, 0x01, 0x02, 0x63, b'a', b'b', b'c'];
+        assert_eq!(decode_cbor::<Vec<u8>>(&bytes).unwrap(), vec![1, 2]);
+        let error = decode_cbor_exact::<Vec<u8>>(&bytes).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::TrailingBytes(4));
+        assert_eq!(error.offset, 3);
+        assert_eq!(decode_cbor_exact::<Vec<u8>>(&bytes[..3]).unwrap(), vec![1, 2]);
+
+        let (numbers, rest) = decode_cbor_prefix::<Vec<u8>>(&bytes).unwrap();
+        assert_eq!(numbers, vec![1, 2]);
+        let (text, rest) = decode_cbor_prefix::<String>(rest).unwrap();
+        assert_eq!(text, "abc");
+        assert!(rest.is_empty());
+        assert!(decode_cbor_prefix::<String>(rest).is_err());
+    }
+
+    #[test]
+    fn test_validate_and_skip() {
+        let items: Vec<Vec<u8>> = vec![
+            vec![0x00], vec![0x18, 0x18], vec![0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            vec![0x43, 1, 2, 3], vec![0x62, b'h', b'i'], vec![0x5f, 0x41, 1, 0x40, 0xff], vec![0x7f, 0x61, b'a', 0xff],
+            vec![0x83, 1, 0x82, 2, 3, 0x80], vec![0xa2, 1, 2, 0x61, b'k', 0xa0], vec![0x9f, 1, 0xbf, 2, 3, 0xff, 0xff],
+            vec![0xc1, 0x1a, 0, 0, 0, 1], vec![0xc2, 0x42, 1, 0], vec![0xd9, 0xd9, 0xf7, 0xc6, 0x80],
+            vec![0xf4], vec![0xf6], vec![0xf7], vec![0xf0], vec![0xf8, 0xff],
+            vec![0xf9, 0x3c, 0x00], vec![0xfa, 0, 0, 0, 0], vec![0xfb, 0, 0, 0, 0, 0, 0, 0, 0],
+        ];
+        for item in &items {
+            assert_eq!(validate(item), Ok(item.len()), "{:x?}", item);
+            assert_eq!(skip_item(item), Ok(item.len()), "{:x?}", item);
+            if let Ok((_, bytes_read)) = Value::from_cbor_bytes(item) {
+                assert_eq!(bytes_read, item.len(), "{:x?}", item);
+            }
+        }
+        assert_eq!(skip_item(&[0x01, 0x02]), Ok(1));
+        assert_eq!(validate(&[0x82, 0x01, 0x02, 0x03]), Ok(3));
+
+        let ill_formed: [&[u8]; 17] = [
+            &[], &[0x18], &[0x62, b'a'], &[0x82, 0x00], &[0x5f, 0x41, 0x00], &[0xf9, 0x00],
+            &[0x1c], &[0xfc], &[0x1f], &[0x3f], &[0xdf], &[0xff], &[0x81, 0xff], &[0x5f, 0x00, 0xff],
+            &[0x5f, 0x5f, 0xff, 0xff], &[0x7f, 0x41, 0x00, 0xff], &[0xf8, 0x00],
+        ];
+        for item in ill_formed {
+            assert!(validate(item).is_err(), "{:x?}", item);
+            assert!(skip_item(item).is_err(), "{:x?}", item);
+        }
+        assert!(matches!(validate(&[0xbf, 0x00, 0xff]).unwrap_err().kind, CborErrorKind::IllFormed(_)));
+        assert_eq!(skip_item(&[0x82, 0x00]).unwrap_err().kind, CborErrorKind::UnexpectedEnd { needed: 1 });
+
+        // Only validate checks text strings
+        let error = validate(&[0x82, 0x00, 0x62, b'a', 0xff]).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::InvalidUtf8);
+        assert_eq!(error.offset, 4);
+        assert_eq!(skip_item(&[0x82, 0x00, 0x62, b'a', 0xff]), Ok(5));
+
+        // A hundred thousand nested definite and indefinite arrays
+        let mut nested = vec![0x81; 100_000];
+        nested.push(0x00);
+        assert_eq!(skip_item(&nested), Ok(nested.len()));
+        let mut nested = vec![0x9f; 100_000];
+        nested.extend(vec![0xff; 100_000]);
+        assert_eq!(validate(&nested), Ok(nested.len()));
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        // Deep nesting fails at the default depth instead of overflowing the stack
+        let mut nested = vec![0x81; 100_000];
+        nested.push(0x00);
+        let error = decode_cbor::<Value>(&nested).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::DepthExceeded(128));
+        assert_eq!(error.offset, 128);
+        let mut tags = vec![0xc6; 100_000];
+        tags.push(0x00);
+        assert_eq!(decode_cbor::<Value>(&tags).unwrap_err().kind, CborErrorKind::DepthExceeded(128));
+
+        let bytes = vec![vec![1u8]].to_cbor_bytes();
+        let options = DecodeOptions { max_depth: 1, ..Default::default() };
+        assert_eq!(decode_cbor_with::<Vec<Vec<u8>>>(&bytes, &options).unwrap_err().path, vec![PathSegment::Index(0)]);
+        let error = decode_cbor_with::<Item>(&Item::Int(vec![1]).to_cbor_bytes(), &options).unwrap_err();
+        assert_eq!((error.kind, error.offset), (CborErrorKind::DepthExceeded(1), 1));
+        let options = DecodeOptions { max_depth: 2, ..Default::default() };
+        assert_eq!(decode_cbor_with::<Vec<Vec<u8>>>(&bytes, &options).unwrap(), vec![vec![1]]);
+
+        // A huge declared length is rejected before anything is read
+        let options = DecodeOptions { max_collection_len: 2, ..Default::default() };
+        let huge = [0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert!(matches!(decode_cbor_with::<Vec<u8>>(&huge, &options).unwrap_err().kind, CborErrorKind::LengthTooLarge(_)));
+        let error = decode_cbor_with::<Vec<u8>>(&[0x9f, 0x01, 0x02, 0x03, 0xff], &options).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::LengthTooLarge(3));
+        assert_eq!(error.offset, 3);
+        assert!(decode_cbor_with::<HashMap<u8, u8>>(&[0xa3, 0x01, 0x01, 0x02, 0x02, 0x03, 0x03], &options).is_err());
+
+        let options = DecodeOptions { max_string_len: 4, ..Default::default() };
+        let bytes = vec!["abcd".to_owned(), "abcde".to_owned()].to_cbor_bytes();
+        let error = decode_cbor_with::<Vec<String>>(&bytes, &options).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::LengthTooLarge(5));
+        assert_eq!(error.path, vec![PathSegment::Index(1)]);
+        assert!(decode_cbor_borrowed_with::<Vec<&str>>(&bytes, &options).is_err());
+
+        // Collections count the size of their items, strings their length
+        let bytes = vec!["abcd".to_owned(), "efgh".to_owned()].to_cbor_bytes();
+        let needed = 2 * std::mem::size_of::<String>() + 8;
+        let options = DecodeOptions { max_allocation: needed, ..Default::default() };
+        assert!(decode_cbor_with::<Vec<String>>(&bytes, &options).is_ok());
+        let options = DecodeOptions { max_allocation: needed - 1, ..Default::default() };
+        let error = decode_cbor_with::<Vec<String>>(&bytes, &options).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::AllocationExceeded(needed - 1));
+        assert_eq!(error.offset, 6);
+
+        // Borrowed strings aren't copied, so they don't count
+        let mut limits = Limits::default();
+        <Vec<&str> as CborDecode>::from_cbor_slice_with(&bytes, &mut limits).unwrap();
+        assert_eq!(limits.allocated(), 2 * std::mem::size_of::<&str>());
+    }
+
+    #[test]
+    fn test_borrowed() {
+        let bytes = vec!["one".to_owned(), "two".to_owned()].to_cbor_bytes();
+        let words: Vec<&str> = decode_cbor_borrowed(&bytes).unwrap();
+        assert_eq!(words, vec!["one", "two"]);
+        assert!(std::ptr::eq(words[0].as_ptr(), &bytes[2]));
+
+        let bytes = byteslice_to_cbor(&[1, 2, 3]);
+        let content: &[u8] = decode_cbor_borrowed(&bytes).unwrap();
+        assert_eq!(content, &[1, 2, 3]);
+
+        // Indefinite-length strings only decode into Cow, as an owned value
+        let chunked = [0x7f, 0x61, b'a', 0x61, b'b', 0xff];
+        assert!(decode_cbor_borrowed::<&str>(&chunked).is_err());
+        assert_eq!(decode_cbor_borrowed::<Cow<str>>(&chunked).unwrap(), Cow::<str>::Owned("ab".to_owned()));
+        assert!(decode_cbor_borrowed::<Cow<str>>(&bytes[..0]).is_err());
+        assert!(matches!(decode_cbor_borrowed::<Cow<[u8]>>(&bytes).unwrap(), Cow::Borrowed(_)));
+        assert_eq!(decode_cbor_borrowed::<Cow<[u8]>>(&[0x5f, 0x41, 0x01, 0xff]).unwrap().as_ref(), &[1]);
+
+        let mut map = BTreeMap::new();
+        map.insert("key".to_owned(), Tagged::new(24, byteslice_to_cbor(&[0xff])));
+        let bytes = map.to_cbor_bytes();
+        let borrowed: BTreeMap<&str, Tagged<Vec<u8>>> = decode_cbor_borrowed(&bytes).unwrap();
+        assert_eq!(borrowed["key"].tag, 24);
+
+        let error = decode_cbor_borrowed::<Vec<&str>>(&[0x82, 0x61, b'a', 0x61, 0xff]).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::InvalidUtf8);
+        assert_eq!(error.offset, 4);
+        assert_eq!(error.path, vec![PathSegment::Index(1)]);
+    }
+
+    #[test]
+    fn test_tagged() {
+        let epoch = Tagged::new(1, 1363896240u64);
+        let bytes = epoch.to_cbor_bytes();
+        assert_eq!(bytes, vec![0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]);
+        assert_eq!(decode_cbor::<Tagged<u64>>(&bytes).unwrap(), epoch);
+
+        for tag in [0, 6, 20, 23, 24, 255, 256, 55799, 65536, u32::MAX as u64 + 1, u64::MAX] {
+            let tagged = Tagged::new(tag, "x".to_owned());
+            let bytes = tagged.to_cbor_bytes();
+            assert_eq!(decode_cbor::<Tagged<String>>(&bytes).unwrap(), tagged);
+            assert_eq!(decode_cbor::<Value>(&bytes).unwrap(), Value::Tag(tag, Box::new(Value::Text("x".to_owned()))));
+        }
+        assert_eq!(read_tag(&[0xd8, 0x20]).unwrap(), (32, 2));
+        assert_eq!(read_tag(&[0xc6]).unwrap(), (6, 1));
+        assert_eq!(read_tag(&[0xd5]).unwrap(), (21, 1));
+        assert!(read_tag(&[0x06]).is_err());
+        assert!(read_tag(&[0xdc]).is_err());
+
+        let nested = Tagged::new(55799, Tagged::new(32, "http://a".to_owned()));
+        assert_eq!(decode_cbor::<Tagged<Tagged<String>>>(&nested.to_cbor_bytes()).unwrap(), nested);
+
+        let error = decode_cbor::<Item>(&[0xc9, 0x80]).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::UnknownVariant("9 for Item. Expected either 6, 7, or 8".to_owned()));
+    }
+
+    #[test]
+    fn test_value() {
+        let value = Value::Map(vec![
+            (Value::Text("id".to_owned()), Value::Unsigned(7)),
+            (Value::Unsigned(1), Value::Array(vec![
+                Value::Negative(u64::MAX),
+                Value::Bytes(vec![0, 255]),
+                Value::Float(1.5, FloatWidth::Single),
+                Value::Float(0.1, FloatWidth::Double),
+                Value::Null,
+                Value::Undefined,
+                Value::Bool(true),
+                Value::Simple(16),
+                Value::Simple(255),
+            ])),
+            (Value::Array(vec![]), Value::Tag(1, Box::new(Value::Unsigned(1700000000)))),
+            (Value::Text("big tag".to_owned()), Value::Tag(55799, Box::new(Value::Map(vec![])))),
+        ]);
+        let bytes = value.to_cbor_bytes();
+        let decoded: Value = decode_cbor(&bytes).unwrap();
+        assert_eq!(decoded, value);
+        assert_eq!(decoded.to_cbor_bytes(), bytes);
+        assert_eq!(decoded.get("id").and_then(Value::as_integer), Some(7));
+        assert_eq!(Value::Negative(u64::MAX).as_integer(), Some(-18446744073709551616));
+
+        // Items the typed impls reject
+        let indefinite = [0xbf, 0x61, b'a', 0x9f, 0x5f, 0x41, 0x01, 0xff, 0xff, 0xff];
+        let decoded: Value = decode_cbor(&indefinite).unwrap();
+        assert_eq!(decoded, Value::Map(vec![(Value::Text("a".to_owned()), Value::Array(vec![Value::Bytes(vec![1])]))]));
+        assert_eq!(decode_cbor::<Value>(&[0xf9, 0x3c, 0x00]).unwrap(), Value::Float(1.0, FloatWidth::Half));
+        assert_eq!(decode_cbor::<Value>(&[0xf9, 0xfc, 0x00]).unwrap(), Value::Float(f64::NEG_INFINITY, FloatWidth::Half));
+
+        // Floats are written back in the precision they were decoded from, NaN payloads included
+        let floats: [&[u8]; 6] = [
+            &[0xfb, 0x3f, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            &[0xf9, 0x3e, 0x00],
+            &[0xfa, 0x3f, 0xc0, 0x00, 0x00],
+            &[0xf9, 0x7e, 0x01],
+            &[0xfa, 0xff, 0x80, 0x00, 0x01],
+            &[0xfb, 0x7f, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01],
+        ];
+        for bytes in floats {
+            assert_eq!(decode_cbor::<Value>(bytes).unwrap().to_cbor_bytes(), bytes);
+        }
+        // A width too narrow for the value falls back to the next one that holds it
+        assert_eq!(Value::Float(0.1, FloatWidth::Half).to_cbor_bytes(), 0.1f64.to_cbor_bytes());
+        assert_eq!(Value::Float(1.0e10, FloatWidth::Half).to_cbor_bytes(), [0xfa, 0x50, 0x15, 0x02, 0xf9]);
+        assert_eq!(decode_cbor::<Value>(&[0xf0]).unwrap(), Value::Simple(16));
+        assert_eq!(decode_cbor::<Value>(&[0xd8, 0x20, 0x60]).unwrap(), Value::Tag(32, Box::new(Value::Text(String::new()))));
+
+        assert!(decode_cbor::<Value>(&[0xf8, 0x10]).is_err());
+        assert!(decode_cbor::<Value>(&[0x1c]).is_err());
+        assert!(decode_cbor::<Value>(&[0xff]).is_err());
+        assert!(decode_cbor::<Value>(&[0xfc]).is_err());
+    }
+
+    #[test]
+    fn test_random_input_does_not_panic() {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        for _ in 0..20_000 {
+            let len = rng.gen_range(0..16);
+            let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            let _ = decode_cbor::<Vec<String>>(&bytes);
+            let _ = decode_cbor::<HashMap<i64, Vec<u8>>>(&bytes);
+            let _ = decode_cbor::<BTreeSet<u16>>(&bytes);
+            let _ = decode_cbor::<Item>(&bytes);
+            let _ = decode_cbor::<f64>(&bytes);
+            let _ = decode_cbor::<Value>(&bytes);
+            let _ = byteslice_from_cbor(&bytes);
+        }
+    }
+
     #[test]
     fn test_small_array() {
         let array = vec![1,2,3];
@@ -1010,6 +2782,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn test_slice() {
         let array: Vec<usize> = vec![1,2,3,4,5,6,7,8,9,1,2,3,4,5,6,7,8,9,1,2,3,4,5,6,7,8,9,];
         let encoded_array = (&array).to_cbor_bytes();
@@ -1049,6 +2822,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(unused_mut)]
     fn test_enum() {
         let mut item = Item::Int(vec![1,2,10]);
         let bytes = item.to_cbor_bytes();
@@ -1097,4 +2871,56 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_shortest_unsigned_integers() {
+        assert_eq!(0u64.to_cbor_bytes(), vec![0x00]);
+        assert_eq!(23u16.to_cbor_bytes(), vec![0x17]);
+        assert_eq!(24u32.to_cbor_bytes(), vec![0x18, 0x18]);
+        assert_eq!(1000usize.to_cbor_bytes(), vec![0x19, 0x03, 0xe8]);
+        assert_eq!(1000000u64.to_cbor_bytes(), vec![0x1a, 0x00, 0x0f, 0x42, 0x40]);
+        assert_eq!(u64::MAX.to_cbor_bytes(), vec![0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(vec![1usize, 2, 3].to_cbor_bytes(), vec![0x83, 0x01, 0x02, 0x03]);
+
+        // Any head width that fits the target type is accepted
+        assert_eq!(decode_cbor::<u64>(&[0x05]).unwrap(), 5);
+        assert_eq!(decode_cbor::<u8>(&[0x1b, 0, 0, 0, 0, 0, 0, 0, 0xff]).unwrap(), 255);
+        assert_eq!(decode_cbor::<u16>(&[0x1a, 0, 0, 0x01, 0x00]).unwrap(), 256);
+        assert!(decode_cbor::<u8>(&[0x19, 0x01, 0x00]).is_err());
+        assert!(decode_cbor::<u32>(&[0x20]).is_err());
+
+        for num in [0, 23, 24, 255, 256, 65535, 65536, 4294967295, 4294967296, u64::MAX] {
+            assert_eq!(decode_cbor::<u64>(&num.to_cbor_bytes()).unwrap(), num);
+            assert_eq!(decode_cbor::<usize>(&(num as usize).to_cbor_bytes()).unwrap(), num as usize);
+        }
+    }
+
+    #[test]
+    fn test_negative_integers() {
+        assert_eq!((-1i8).to_cbor_bytes(), vec![0x20]);
+        assert_eq!((-24i8).to_cbor_bytes(), vec![0x37]);
+        assert_eq!((-100i16).to_cbor_bytes(), vec![0x38, 0x63]);
+        assert_eq!((-1000i32).to_cbor_bytes(), vec![0x39, 0x03, 0xe7]);
+        assert_eq!(i64::MIN.to_cbor_bytes(), vec![0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        // Values written by other encoders
+        assert_eq!(decode_cbor::<i8>(&[0x29]).unwrap(), -10);
+        assert_eq!(decode_cbor::<i16>(&[0x39, 0x03, 0xe7]).unwrap(), -1000);
+        assert_eq!(decode_cbor::<i64>(&[0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap(), i64::MIN);
+        assert!(decode_cbor::<i64>(&[0x3b, 0x80, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(decode_cbor::<i8>(&[0x38, 0x80]).is_err());
+
+        for num in [i8::MIN, -25, -24, -1, 0, 23, 24, i8::MAX] {
+            assert_eq!(decode_cbor::<i8>(&num.to_cbor_bytes()).unwrap(), num);
+        }
+        for num in [i16::MIN, -257, -256, -25, -1, 0, i16::MAX] {
+            assert_eq!(decode_cbor::<i16>(&num.to_cbor_bytes()).unwrap(), num);
+        }
+        for num in [i32::MIN, -65537, -65536, -1, 0, i32::MAX] {
+            assert_eq!(decode_cbor::<i32>(&num.to_cbor_bytes()).unwrap(), num);
+        }
+        for num in [i64::MIN, -4294967297, -4294967296, -1, 0, i64::MAX] {
+            assert_eq!(decode_cbor::<i64>(&num.to_cbor_bytes()).unwrap(), num);
+        }
+    }
+
 }
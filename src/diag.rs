@@ -0,0 +1,1284 @@
+//! Diagnostic notation (RFC 8949 section 8).
+//!
+//! The printer and the parser keep open arrays, maps and tags on a heap stack instead of
+//! recursing, so how deeply input may nest is bounded only by memory.
+
+use crate::cbor::{
+    array_at, byte_at, bytes_at, decode_cbor_exact, expected_data_item, f16_to_f64, read_argument, write_head,
+    write_shortest_float, CborError, CborErrorKind, DataItem, Value,
+};
+use crate::base64::decode_base64;
+
+/// Renders the data item in `bytes` in diagnostic notation (RFC 8949 section 8), on one line.
+///
+/// Heads that are longer than they need to be and floats get an encoding indicator
+/// (`_0` to `_3`), so the notation says exactly how the item was encoded.
+/// The item must be well-formed, text strings must be valid utf-8, and nothing may follow it.
+pub fn to_diagnostic(bytes: &[u8]) -> Result<String, CborError> {
+    Printer::new(false).print(bytes)
+}
+
+/// Like `to_diagnostic`, but every item of a non-empty array or map goes on its own line,
+/// indented by two spaces per level.
+pub fn to_diagnostic_pretty(bytes: &[u8]) -> Result<String, CborError> {
+    Printer::new(true).print(bytes)
+}
+
+/// An array, map or tag that is printed but not closed yet.
+enum Open {
+    Container {
+        map: bool,
+        /// Items left to read, or None for indefinite length. Maps hold two per entry.
+        remaining: Option<u128>,
+        items: u128,
+        /// Whether the opening bracket is followed by `_` or an encoding indicator.
+        marked: bool,
+    },
+    Tag,
+}
+
+/// Prints data items in diagnostic notation into `out`, indented over several lines when `pretty`.
+struct Printer {
+    out: String,
+    pretty: bool,
+    open: Vec<Open>,
+    depth: usize,
+}
+
+impl Printer {
+    fn new(pretty: bool) -> Self {
+        Printer { out: String::new(), pretty, open: Vec::new(), depth: 0 }
+    }
+
+    fn print(mut self, bytes: &[u8]) -> Result<String, CborError> {
+        let mut i = 0;
+        loop {
+            let initial_byte = byte_at(bytes, i)?;
+            if initial_byte == 0xff {
+                match self.open.pop() {
+                    Some(Open::Container { map, remaining: None, items, marked }) => {
+                        if map && !items.is_multiple_of(2) {
+                            return Err(CborError::new(CborErrorKind::IllFormed("Indefinite-length map ends after a key without a value".to_owned())).at(i))
+                        }
+                        self.close(map, items, marked);
+                        i += 1;
+                    },
+                    _ => return Err(CborError::new(CborErrorKind::IllFormed("\"break\" outside of an indefinite length item".to_owned())).at(i)),
+                }
+            } else {
+                self.separator();
+                if let Some(bytes_read) = self.open_item(bytes, i)? {
+                    i += bytes_read;
+                    continue
+                }
+                i += self.item(bytes, i).map_err(|e| e.at(i))?;
+            }
+            // A whole item has been printed, which may complete the items it is in
+            loop {
+                match self.open.last_mut() {
+                    None => {
+                        if i < bytes.len() {
+                            return Err(CborError::new(CborErrorKind::TrailingBytes(bytes.len() - i)).at(i))
+                        }
+                        return Ok(self.out)
+                    },
+                    Some(Open::Tag) => {
+                        self.open.pop();
+                        self.out.push(')');
+                    },
+                    Some(Open::Container { remaining: Some(remaining), items, .. }) if *items + 1 == *remaining => {
+                        if let Some(Open::Container { map, items, marked, .. }) = self.open.pop() {
+                            self.close(map, items + 1, marked);
+                        }
+                    },
+                    Some(Open::Container { items, .. }) => {
+                        *items += 1;
+                        break
+                    },
+                }
+            }
+        }
+    }
+
+    /// Writes whatever goes between the previous item and the next one.
+    fn separator(&mut self) {
+        if let Some(Open::Container { map, items, marked, .. }) = self.open.last() {
+            if *map && !items.is_multiple_of(2) {
+                self.out.push_str(": ");
+                return
+            }
+            if *items > 0 {
+                self.out.push(',');
+            }
+            if self.pretty {
+                self.newline(self.depth);
+            } else if *items > 0 || *marked {
+                self.out.push(' ');
+            }
+        }
+    }
+
+    fn newline(&mut self, depth: usize) {
+        self.out.push('\n');
+        for _ in 0..depth {
+            self.out.push_str("  ");
+        }
+    }
+
+    fn close(&mut self, map: bool, items: u128, marked: bool) {
+        self.depth -= 1;
+        if items > 0 && self.pretty {
+            self.newline(self.depth);
+        } else if items == 0 && marked {
+            self.out.push(' ');
+        }
+        self.out.push(if map { '}' } else { ']' });
+    }
+
+    /// Starts printing a tag or a non-empty array or map at `i`, and returns the size of its head.
+    /// Returns None for any other item.
+    fn open_item(&mut self, bytes: &[u8], i: usize) -> Result<Option<usize>, CborError> {
+        let initial_byte = bytes[i];
+        match (initial_byte >> 5, initial_byte & 0x1f) {
+            (4 | 5, 0x1f) => {
+                let map = initial_byte >> 5 == 5;
+                self.out.push_str(if map { "{_" } else { "[_" });
+                self.open.push(Open::Container { map, remaining: None, items: 0, marked: true });
+                self.depth += 1;
+                Ok(Some(1))
+            },
+            (4 | 5, _) => {
+                let (len, head_len) = read_argument(&bytes[i..]).map_err(|e| e.at(i))?;
+                if len == 0 {
+                    return Ok(None)
+                }
+                let map = initial_byte >> 5 == 5;
+                let indicator = encoding_indicator(initial_byte, len, head_len);
+                self.out.push(if map { '{' } else { '[' });
+                self.out.push_str(indicator);
+                let remaining = if map { len as u128 * 2 } else { len as u128 };
+                self.open.push(Open::Container { map, remaining: Some(remaining), items: 0, marked: !indicator.is_empty() });
+                self.depth += 1;
+                Ok(Some(head_len))
+            },
+            (6, _) => {
+                let (tag, head_len) = read_argument(&bytes[i..]).map_err(|e| e.at(i))?;
+                self.out.push_str(&tag.to_string());
+                self.out.push_str(encoding_indicator(initial_byte, tag, head_len));
+                self.out.push('(');
+                self.open.push(Open::Tag);
+                Ok(Some(head_len))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Prints the item at `i`, which is not a tag or a non-empty array or map, and returns its size.
+    /// Errors are relative to `i`.
+    fn item(&mut self, bytes: &[u8], i: usize) -> Result<usize, CborError> {
+        let bytes = &bytes[i..];
+        let initial_byte = bytes[0];
+        let major_type = initial_byte >> 5;
+        match (major_type, initial_byte & 0x1f) {
+            (_, 0x1c..0x1f) => Err(CborErrorKind::IllFormed(format!("Reserved initial byte {:x}", initial_byte)).into()),
+            (2 | 3, 0x1f) => {
+                let mut j = 1;
+                let mut chunks = 0;
+                while byte_at(bytes, j)? != 0xff {
+                    let chunk_byte = bytes[j];
+                    if chunk_byte >> 5 != major_type || chunk_byte & 0x1f == 0x1f {
+                        return Err(CborError::new(CborErrorKind::IllFormed(format!(
+                            "Byte {:x} is not a valid chunk of an indefinite-length string of major type {}", chunk_byte, major_type
+                        ))).at(j))
+                    }
+                    self.out.push_str(if chunks == 0 { "(_ " } else { ", " });
+                    j += self.string(&bytes[j..]).map_err(|e| e.at(j))?;
+                    chunks += 1;
+                }
+                match chunks {
+                    0 => self.out.push_str(if major_type == 2 { "''_" } else { "\"\"_" }),
+                    _ => self.out.push(')'),
+                }
+                Ok(j + 1)
+            },
+            (7, 0x1f) => unreachable!("\"break\" is handled by the caller"),
+            (_, 0x1f) => Err(CborErrorKind::IllFormed(format!("Byte {:x} is not followed by a definite argument", initial_byte)).into()),
+            (0, _) => {
+                let (argument, head_len) = read_argument(bytes)?;
+                self.out.push_str(&argument.to_string());
+                self.out.push_str(encoding_indicator(initial_byte, argument, head_len));
+                Ok(head_len)
+            },
+            (1, _) => {
+                let (argument, head_len) = read_argument(bytes)?;
+                self.out.push_str(&(-1 - argument as i128).to_string());
+                self.out.push_str(encoding_indicator(initial_byte, argument, head_len));
+                Ok(head_len)
+            },
+            (2 | 3, _) => self.string(bytes),
+            (4 | 5, _) => {
+                let (len, head_len) = read_argument(bytes)?;
+                debug_assert_eq!(len, 0);
+                let indicator = encoding_indicator(initial_byte, len, head_len);
+                let (open, close) = if major_type == 5 { ("{", "}") } else { ("[", "]") };
+                self.out.push_str(open);
+                if !indicator.is_empty() {
+                    self.out.push_str(indicator);
+                    self.out.push(' ');
+                }
+                self.out.push_str(close);
+                Ok(head_len)
+            },
+            (7, 20) => { self.out.push_str("false"); Ok(1) },
+            (7, 21) => { self.out.push_str("true"); Ok(1) },
+            (7, 22) => { self.out.push_str("null"); Ok(1) },
+            (7, 23) => { self.out.push_str("undefined"); Ok(1) },
+            (7, 0x18) => match byte_at(bytes, 1)? {
+                0x00..0x20 => Err(CborErrorKind::IllFormed(format!("Simple value {} must be encoded in a single byte", bytes[1])).into()),
+                simple => { self.out.push_str(&format!("simple({})", simple)); Ok(2) },
+            },
+            (7, 0x19) => {
+                let float = f16_to_f64(u16::from_be_bytes(array_at(bytes, 1)?));
+                self.out.push_str(&float_text(float, true));
+                self.out.push_str("_1");
+                Ok(3)
+            },
+            (7, 0x1a) => {
+                let float = f32::from_be_bytes(array_at(bytes, 1)?);
+                self.out.push_str(&float_text(float as f64, true));
+                self.out.push_str("_2");
+                Ok(5)
+            },
+            (7, 0x1b) => {
+                let float = f64::from_be_bytes(array_at(bytes, 1)?);
+                self.out.push_str(&float_text(float, false));
+                self.out.push_str("_3");
+                Ok(9)
+            },
+            (7, simple) => { self.out.push_str(&format!("simple({})", simple)); Ok(1) },
+            _ => unreachable!("tags are handled by open_item"),
+        }
+    }
+
+    /// Prints the definite-length byte or text string at the start of `bytes` and returns its size.
+    fn string(&mut self, bytes: &[u8]) -> Result<usize, CborError> {
+        let (argument, head_len) = read_argument(bytes)?;
+        let len = usize::try_from(argument).map_err(|_| CborError::from(CborErrorKind::LengthTooLarge(argument)))?;
+        let content = bytes_at(bytes, head_len, len)?;
+        if bytes[0] >> 5 == 2 {
+            self.out.push_str("h'");
+            for byte in content {
+                self.out.push_str(&format!("{:02x}", byte));
+            }
+            self.out.push('\'');
+        } else {
+            let text = std::str::from_utf8(content)
+                .map_err(|e| CborError::new(CborErrorKind::InvalidUtf8).at(head_len + e.valid_up_to()))?;
+            write_quoted(&mut self.out, text);
+        }
+        self.out.push_str(encoding_indicator(bytes[0], argument, head_len));
+        Ok(head_len + len)
+    }
+}
+
+/// The encoding indicator for a head that is longer than `argument` needs, or "" for a shortest head.
+fn encoding_indicator(initial_byte: u8, argument: u64, head_len: usize) -> &'static str {
+    let mut shortest = Vec::new();
+    write_head(&mut shortest, initial_byte >> 5, argument);
+    if shortest.len() == head_len {
+        return ""
+    }
+    match head_len {
+        2 => "_0",
+        3 => "_1",
+        5 => "_2",
+        _ => "_3",
+    }
+}
+
+/// Formats a float so that it reads back as the same value. Single and half precision floats
+/// are formatted as single precision, which is shorter and exact for both.
+fn float_text(float: f64, single: bool) -> String {
+    if float.is_nan() {
+        "NaN".to_owned()
+    } else if float.is_infinite() {
+        if float > 0.0 { "Infinity".to_owned() } else { "-Infinity".to_owned() }
+    } else if single {
+        format!("{:?}", float as f32)
+    } else {
+        format!("{:?}", float)
+    }
+}
+
+/// Writes `text` in double quotes, escaped the way JSON escapes strings.
+pub(crate) fn write_quoted(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if c < ' ' || c == '\u{7f}' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Parses diagnostic notation into the CBOR bytes it describes, so that `to_diagnostic` output
+/// reads back as the bytes it came from.
+///
+/// Besides the notation `to_diagnostic` writes, this accepts `b64'..'` and `'..'` byte strings,
+/// hex and base64 spread over several lines, integers in `0x`, `0o` and `0b` notation,
+/// floats without an encoding indicator (written in the shortest width that holds them exactly),
+/// and comments, either between slashes or from `#` to the end of the line.
+/// Error offsets are in bytes of `text`.
+pub fn from_diagnostic(text: &str) -> Result<Vec<u8>, CborError> {
+    Parser { text, pos: 0, out: Vec::new(), heads: Vec::new(), open: Vec::new(), last_string: None }.parse()
+}
+
+/// Parses diagnostic notation into a `Value`.
+pub fn value_from_diagnostic(text: &str) -> Result<Value, CborError> {
+    decode_cbor_exact(&from_diagnostic(text)?)
+}
+
+/// The head of an array, map or indefinite-length string. It is inserted into the output
+/// at `position` once the number of items is known.
+struct Head {
+    position: usize,
+    major_type: u8,
+    indicator: Option<u8>,
+    /// None for indefinite length.
+    len: Option<u64>,
+}
+
+/// An array, map, indefinite-length string or tag that is being parsed.
+enum Frame {
+    /// `head` indexes `Parser::heads`, and `offset` is where the opening bracket is in the text.
+    Container { map: bool, head: usize, items: u64, offset: usize },
+    /// The major type is that of the first chunk.
+    Chunks { head: usize, major_type: Option<u8> },
+    Tag,
+}
+
+/// Parses diagnostic notation into CBOR. The heads of arrays, maps and indefinite-length strings
+/// are inserted at the end, once their lengths are known.
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    /// The encoded items, without the heads in `heads`.
+    out: Vec<u8>,
+    heads: Vec<Head>,
+    open: Vec<Frame>,
+    /// The major type of the last item, if it was a definite-length string.
+    last_string: Option<u8>,
+}
+
+impl Parser<'_> {
+    fn parse(mut self) -> Result<Vec<u8>, CborError> {
+        loop {
+            self.skip_space()?;
+            self.last_string = None;
+            let item_start = self.pos;
+            if !self.item()? {
+                continue
+            }
+            // A whole item has been parsed, which may complete the items it is in
+            loop {
+                self.skip_space()?;
+                match self.open.last_mut() {
+                    None => {
+                        if let Some(c) = self.peek() {
+                            return Err(self.error(format!("Unexpected {:?} after the data item", c)))
+                        }
+                        return Ok(self.finish())
+                    },
+                    Some(Frame::Tag) => {
+                        self.expect(')')?;
+                        self.open.pop();
+                    },
+                    Some(Frame::Chunks { head, major_type }) => {
+                        match (self.last_string, *major_type) {
+                            (Some(chunk), None) => {
+                                *major_type = Some(chunk);
+                                self.heads[*head].major_type = chunk;
+                            },
+                            (Some(chunk), Some(major_type)) if chunk == major_type => {},
+                            _ => return Err(CborError::new(CborErrorKind::InvalidDiagnostic(
+                                "Chunks of an indefinite-length string must be definite-length strings of the same type".to_owned()
+                            )).at(item_start)),
+                        }
+                        match self.peek() {
+                            Some(',') => {
+                                self.pos += 1;
+                                break
+                            },
+                            Some(')') => {
+                                self.pos += 1;
+                                self.open.pop();
+                                self.out.push(0xff);
+                            },
+                            _ => return Err(self.error("Expected ',' or ')'".to_owned())),
+                        }
+                    },
+                    Some(Frame::Container { map, items, .. }) => {
+                        *items += 1;
+                        let close = if *map { '}' } else { ']' };
+                        if *map && *items % 2 == 1 {
+                            self.expect(':')?;
+                            break
+                        }
+                        match self.peek() {
+                            Some(',') => {
+                                self.pos += 1;
+                                break
+                            },
+                            Some(c) if c == close => {
+                                self.pos += 1;
+                                self.close_container()?;
+                            },
+                            _ => return Err(self.error(format!("Expected ',' or '{}'", close))),
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// Parses a data item, or the start of one that holds others.
+    /// Returns whether the item is complete.
+    fn item(&mut self) -> Result<bool, CborError> {
+        let start = self.pos;
+        let Some(c) = self.peek() else {
+            return Err(self.error("Expected a data item".to_owned()))
+        };
+        if matches!(self.open.last(), Some(Frame::Chunks { .. })) && !matches!(c, '"' | '\'' | 'h' | 'b') {
+            return Err(self.error("Chunks of an indefinite-length string must be definite-length strings of the same type".to_owned()))
+        }
+        match c {
+            '[' | '{' => {
+                self.pos += 1;
+                let map = c == '{';
+                let (indefinite, indicator) = match self.peek() {
+                    Some('_') => match self.indicator()? {
+                        Some(indicator) => (false, Some(indicator)),
+                        None => {
+                            self.pos += 1;
+                            (true, None)
+                        },
+                    },
+                    _ => (false, None),
+                };
+                self.heads.push(Head {
+                    position: self.out.len(),
+                    major_type: if map { 5 } else { 4 },
+                    indicator,
+                    len: if indefinite { None } else { Some(0) },
+                });
+                self.open.push(Frame::Container { map, head: self.heads.len() - 1, items: 0, offset: start });
+                self.skip_space()?;
+                if self.peek() == Some(if map { '}' } else { ']' }) {
+                    self.pos += 1;
+                    self.close_container()?;
+                    return Ok(true)
+                }
+                Ok(false)
+            },
+            '(' => {
+                self.pos += 1;
+                self.expect('_')?;
+                self.heads.push(Head { position: self.out.len(), major_type: 2, indicator: None, len: None });
+                self.open.push(Frame::Chunks { head: self.heads.len() - 1, major_type: None });
+                self.skip_space()?;
+                if self.peek() == Some(')') {
+                    return Err(self.error("An empty indefinite-length string is written ''_ or \"\"_".to_owned()))
+                }
+                Ok(false)
+            },
+            '"' | '\'' => {
+                let content = self.quoted(c)?;
+                let major_type = if c == '"' { 3 } else { 2 };
+                self.string(major_type, content.as_bytes(), start)?;
+                Ok(true)
+            },
+            c if c.is_ascii_alphanumeric() || c == '-' => self.word(start),
+            c => Err(self.error(format!("Unexpected {:?}", c))),
+        }
+    }
+
+    /// Parses an item that starts with a letter, digit or minus sign.
+    fn word(&mut self, start: usize) -> Result<bool, CborError> {
+        let end = self.text[start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-')))
+            .map_or(self.text.len(), |len| start + len);
+        let word = &self.text[start..end];
+        self.pos = end;
+        match word {
+            "h" if self.peek() == Some('\'') => {
+                let content = self.hex(self.pos + 1)?;
+                self.string(2, &content, start)?;
+            },
+            "b64" if self.peek() == Some('\'') => {
+                let content_start = self.pos + 1;
+                let Some(len) = self.text[content_start..].find('\'') else {
+                    return Err(self.error("Unterminated byte string".to_owned()))
+                };
+                let content = decode_base64(&self.text[content_start..content_start + len])
+                    .ok_or_else(|| CborError::new(CborErrorKind::InvalidDiagnostic("Invalid base64".to_owned())).at(content_start))?;
+                self.pos = content_start + len + 1;
+                self.string(2, &content, start)?;
+            },
+            "false" => self.out.push(0xf4),
+            "true" => self.out.push(0xf5),
+            "null" => self.out.push(0xf6),
+            "undefined" => self.out.push(0xf7),
+            "simple" => {
+                self.expect('(')?;
+                self.skip_space()?;
+                let number_start = self.pos;
+                let number_end = self.text[number_start..].find(|c: char| !c.is_ascii_digit()).map_or(self.text.len(), |len| number_start + len);
+                match self.text[number_start..number_end].parse::<u8>() {
+                    Ok(simple @ 0..24) => self.out.push(0xe0 | simple),
+                    Ok(simple @ 32..) => self.out.extend_from_slice(&[0xf8, simple]),
+                    _ => return Err(self.error("Simple values are 0 to 23 and 32 to 255".to_owned())),
+                }
+                self.pos = number_end;
+                self.skip_space()?;
+                self.expect(')')?;
+            },
+            "Infinity" | "-Infinity" | "NaN" => self.float(word, start)?,
+            _ if word.starts_with(|c: char| c.is_ascii_digit() || c == '-') => {
+                let digits = word.strip_prefix('-').unwrap_or(word);
+                let radix = match digits.get(..2) {
+                    Some("0x" | "0X") => 16,
+                    Some("0o" | "0O") => 8,
+                    Some("0b" | "0B") => 2,
+                    _ => 10,
+                };
+                if radix == 10 && word.contains(['.', 'e', 'E']) {
+                    self.float(word, start)?;
+                    return Ok(true)
+                }
+                let digits = if radix == 10 { digits } else { &digits[2..] };
+                if !digits.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+                    return Err(CborError::new(CborErrorKind::InvalidDiagnostic(format!("{} is not a number", word))).at(start))
+                }
+                let magnitude = u128::from_str_radix(digits, radix).ok().filter(|magnitude| *magnitude <= 1 << 64)
+                    .ok_or_else(|| CborError::new(CborErrorKind::InvalidDiagnostic(format!("{} is not an integer of 64 bits", word))).at(start))?;
+                let (major_type, argument) = match (word.starts_with('-'), magnitude) {
+                    (true, 0) => (0, 0),
+                    (true, magnitude) => (1, magnitude - 1),
+                    (false, magnitude) => (0, magnitude),
+                };
+                let argument = u64::try_from(argument)
+                    .map_err(|_| CborError::new(CborErrorKind::InvalidDiagnostic(format!("{} is not an integer of 64 bits", word))).at(start))?;
+                let indicator = self.indicator()?;
+                if self.peek() == Some('(') {
+                    if major_type != 0 {
+                        return Err(CborError::new(CborErrorKind::InvalidDiagnostic("Tag numbers can't be negative".to_owned())).at(start))
+                    }
+                    self.head(6, argument, indicator, start)?;
+                    self.pos += 1;
+                    self.open.push(Frame::Tag);
+                    return Ok(false)
+                }
+                self.head(major_type, argument, indicator, start)?;
+            },
+            _ => return Err(CborError::new(CborErrorKind::InvalidDiagnostic(format!("Unexpected {:?}", word))).at(start)),
+        }
+        Ok(true)
+    }
+
+    /// Writes a float in the width its encoding indicator asks for, or else the shortest exact one.
+    fn float(&mut self, word: &str, start: usize) -> Result<(), CborError> {
+        let invalid = |message: String| CborError::new(CborErrorKind::InvalidDiagnostic(message)).at(start);
+        let float = match word {
+            "Infinity" => f64::INFINITY,
+            "-Infinity" => f64::NEG_INFINITY,
+            "NaN" => f64::NAN,
+            _ => word.parse::<f64>().map_err(|_| invalid(format!("{} is not a number", word)))?,
+        };
+        match self.indicator()? {
+            None => write_shortest_float(&mut self.out, float),
+            Some(1) => {
+                let half = match float {
+                    f64::INFINITY => Some(0x7c00),
+                    f64::NEG_INFINITY => Some(0xfc00),
+                    _ if float.is_nan() => Some(0x7e00),
+                    _ => round_to_f16(float),
+                };
+                let half = half.ok_or_else(|| invalid(format!("{} is too large for half precision", word)))?;
+                self.out.push(0xf9);
+                self.out.extend_from_slice(&half.to_be_bytes());
+            },
+            Some(2) => {
+                // Parsing again rounds to single precision directly, rather than through f64
+                let single = word.parse::<f32>().unwrap_or(float as f32);
+                if single.is_infinite() && float.is_finite() {
+                    return Err(invalid(format!("{} is too large for single precision", word)))
+                }
+                self.out.push(0xfa);
+                self.out.extend_from_slice(&single.to_be_bytes());
+            },
+            Some(3) => {
+                if float.is_infinite() && !word.ends_with("Infinity") {
+                    return Err(invalid(format!("{} is too large for double precision", word)))
+                }
+                self.out.push(0xfb);
+                self.out.extend_from_slice(&float.to_be_bytes());
+            },
+            Some(_) => return Err(invalid("Floats are written with encoding indicator _1, _2 or _3".to_owned())),
+        }
+        Ok(())
+    }
+
+    /// Writes a definite-length string, or an empty indefinite-length one if it is followed by `_`.
+    fn string(&mut self, major_type: u8, content: &[u8], start: usize) -> Result<(), CborError> {
+        let indicator = self.indicator()?;
+        if indicator.is_none() && self.peek() == Some('_') {
+            self.pos += 1;
+            if !content.is_empty() {
+                return Err(CborError::new(CborErrorKind::InvalidDiagnostic("Only an empty string can be followed by _".to_owned())).at(start))
+            }
+            self.out.extend_from_slice(&[major_type << 5 | 0x1f, 0xff]);
+            return Ok(())
+        }
+        self.head(major_type, content.len() as u64, indicator, start)?;
+        self.out.extend_from_slice(content);
+        self.last_string = Some(major_type);
+        Ok(())
+    }
+
+    /// Writes a head whose argument is known now. Errors are at `start`.
+    fn head(&mut self, major_type: u8, argument: u64, indicator: Option<u8>, start: usize) -> Result<(), CborError> {
+        check_indicator(argument, indicator).map_err(|e| e.at(start))?;
+        write_head_with(&mut self.out, major_type, argument, indicator);
+        Ok(())
+    }
+
+    /// Reads an encoding indicator `_0` to `_3` if there is one.
+    fn indicator(&mut self) -> Result<Option<u8>, CborError> {
+        let rest = &self.text.as_bytes()[self.pos..];
+        match rest {
+            [b'_', digit @ b'0'..=b'3', ..] => {
+                self.pos += 2;
+                Ok(Some(digit - b'0'))
+            },
+            [b'_', c, ..] if c.is_ascii_alphanumeric() => Err(self.error("Encoding indicators are _0 to _3".to_owned())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads a quoted string starting at the opening quote, with JSON escapes.
+    fn quoted(&mut self, quote: char) -> Result<String, CborError> {
+        let mut content = String::new();
+        let mut chars = self.text[self.pos + 1..].char_indices();
+        let base = self.pos + 1;
+        loop {
+            let Some((i, c)) = chars.next() else {
+                return Err(self.error("Unterminated string".to_owned()))
+            };
+            match c {
+                c if c == quote => {
+                    self.pos = base + i + 1;
+                    return Ok(content)
+                },
+                '\\' => {
+                    let invalid = || CborError::new(CborErrorKind::InvalidDiagnostic("Invalid escape sequence".to_owned())).at(base + i);
+                    let escaped = match chars.next().ok_or_else(invalid)?.1 {
+                        c @ ('"' | '\'' | '\\' | '/') => c,
+                        'b' => '\u{08}',
+                        'f' => '\u{0c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let high = read_code_unit(&mut chars).ok_or_else(invalid)?;
+                            let units = match high {
+                                0xd800..0xdc00 => {
+                                    if !(chars.next().is_some_and(|(_, c)| c == '\\') && chars.next().is_some_and(|(_, c)| c == 'u')) {
+                                        return Err(invalid())
+                                    }
+                                    vec![high, read_code_unit(&mut chars).ok_or_else(invalid)?]
+                                },
+                                _ => vec![high],
+                            };
+                            char::decode_utf16(units).next().and_then(|c| c.ok()).ok_or_else(invalid)?
+                        },
+                        _ => return Err(invalid()),
+                    };
+                    content.push(escaped);
+                },
+                c => content.push(c),
+            }
+        }
+    }
+
+    /// Decodes the hex digits from `start` up to the closing quote, which may be spaced out and
+    /// commented, and moves past the quote.
+    fn hex(&mut self, start: usize) -> Result<Vec<u8>, CborError> {
+        let mut content = Vec::new();
+        let mut high: Option<u8> = None;
+        self.pos = start;
+        loop {
+            // Comments are skipped before looking for the quote, as they may contain one
+            self.skip_space()?;
+            let c = match self.peek() {
+                Some('\'') => break,
+                Some(c) => c,
+                None => return Err(CborError::new(CborErrorKind::InvalidDiagnostic("Unterminated byte string".to_owned())).at(start - 1)),
+            };
+            let nibble = c.to_digit(16).ok_or_else(|| self.error(format!("Unexpected {:?} in hex", c)))? as u8;
+            match high.take() {
+                Some(high) => content.push(high << 4 | nibble),
+                None => high = Some(nibble),
+            }
+            self.pos += 1;
+        }
+        if high.is_some() {
+            return Err(CborError::new(CborErrorKind::InvalidDiagnostic("Hex must have an even number of digits".to_owned())).at(start))
+        }
+        self.pos += 1;
+        Ok(content)
+    }
+
+    /// Ends the array or map on top of `open` once its closing bracket has been read.
+    fn close_container(&mut self) -> Result<(), CborError> {
+        let Some(Frame::Container { map, head, items, offset }) = self.open.pop() else {
+            unreachable!("only called for containers")
+        };
+        let head = &mut self.heads[head];
+        match head.len {
+            None => self.out.push(0xff),
+            Some(_) => {
+                let len = if map { items / 2 } else { items };
+                check_indicator(len, head.indicator).map_err(|e| e.at(offset))?;
+                head.len = Some(len);
+            },
+        }
+        Ok(())
+    }
+
+    /// Inserts the heads of arrays, maps and indefinite-length strings into the output.
+    fn finish(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.out.len() + self.heads.len());
+        let mut written = 0;
+        for head in self.heads {
+            bytes.extend_from_slice(&self.out[written..head.position]);
+            written = head.position;
+            match head.len {
+                Some(len) => write_head_with(&mut bytes, head.major_type, len, head.indicator),
+                None => bytes.push(head.major_type << 5 | 0x1f),
+            }
+        }
+        bytes.extend_from_slice(&self.out[written..]);
+        bytes
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_space(&mut self) -> Result<(), CborError> {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => self.pos += c.len_utf8(),
+                Some('/') => match self.text[self.pos + 1..].find('/') {
+                    Some(len) => self.pos += len + 2,
+                    None => return Err(self.error("Unterminated comment".to_owned())),
+                },
+                Some('#') => self.pos = self.text[self.pos..].find('\n').map_or(self.text.len(), |len| self.pos + len + 1),
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), CborError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            },
+            _ => Err(self.error(format!("Expected {:?}", expected))),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn error(&self, message: String) -> CborError {
+        CborError::new(CborErrorKind::InvalidDiagnostic(message)).at(self.pos)
+    }
+}
+
+/// Reads the four hex digits of a `\u` escape.
+fn read_code_unit(chars: &mut std::str::CharIndices) -> Option<u16> {
+    let hex: String = chars.take(4).map(|(_, c)| c).collect();
+    u16::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 4)
+}
+
+/// Checks that `argument` fits the width an encoding indicator asks for.
+fn check_indicator(argument: u64, indicator: Option<u8>) -> Result<(), CborError> {
+    let max = match indicator {
+        None | Some(3) => return Ok(()),
+        Some(0) => u8::MAX as u64,
+        Some(1) => u16::MAX as u64,
+        _ => u32::MAX as u64,
+    };
+    match argument <= max {
+        true => Ok(()),
+        false => Err(CborErrorKind::InvalidDiagnostic(format!("{} does not fit encoding indicator _{}", argument, indicator.unwrap_or(0))).into()),
+    }
+}
+
+/// Writes a head in the width an encoding indicator asks for, or else the shortest one.
+fn write_head_with(bytes: &mut Vec<u8>, major_type: u8, argument: u64, indicator: Option<u8>) {
+    match indicator {
+        None => write_head(bytes, major_type, argument),
+        Some(0) => bytes.extend_from_slice(&[major_type << 5 | 0x18, argument as u8]),
+        Some(1) => {
+            bytes.push(major_type << 5 | 0x19);
+            bytes.extend_from_slice(&(argument as u16).to_be_bytes());
+        },
+        Some(2) => {
+            bytes.push(major_type << 5 | 0x1a);
+            bytes.extend_from_slice(&(argument as u32).to_be_bytes());
+        },
+        Some(_) => {
+            bytes.push(major_type << 5 | 0x1b);
+            bytes.extend_from_slice(&argument.to_be_bytes());
+        },
+    }
+}
+
+/// Rounds a finite float to the nearest half precision float, ties to even.
+/// Returns None if it is too large for half precision.
+fn round_to_f16(float: f64) -> Option<u16> {
+    let sign = if float.is_sign_negative() { 0x8000 } else { 0 };
+    let magnitude = float.abs();
+    if magnitude >= 65520.0 {
+        return None
+    }
+    // Finite halves are ordered by their bits, so search for the largest one at or below `magnitude`
+    let (mut low, mut high) = (0u16, 0x7bff);
+    while low < high {
+        let middle = low + (high - low).div_ceil(2);
+        if f16_to_f64(middle) <= magnitude { low = middle } else { high = middle - 1 }
+    }
+    let below = low;
+    if below == 0x7bff {
+        return Some(sign | below)
+    }
+    let above = below + 1;
+    let nearest = match (magnitude - f16_to_f64(below)).partial_cmp(&(f16_to_f64(above) - magnitude)) {
+        Some(std::cmp::Ordering::Less) => below,
+        Some(std::cmp::Ordering::Greater) => above,
+        _ => if below.is_multiple_of(2) { below } else { above },
+    };
+    Some(sign | nearest)
+}
+
+/// Explains the data items in `bytes` line by line, in the style of cbor.me: one line per head
+/// or string chunk, indented by nesting, with its hex and a comment saying what it is.
+/// A sequence of several items is annotated item by item.
+///
+/// Malformed input is annotated up to the item that fails, which gets a last line with the
+/// offending byte in brackets and the error.
+pub fn annotate(bytes: &[u8]) -> String {
+    let mut lines = Vec::new();
+    if let Err((start, depth, error)) = annotate_items(bytes, &mut lines) {
+        let mut hex = to_hex(&bytes[start..error.offset.min(bytes.len())]);
+        if let Some(byte) = bytes.get(error.offset) {
+            hex.push_str(&format!("[{:02x}]", byte));
+        }
+        lines.push(Line { depth, hex, comment: format!("error: {}", error) });
+    }
+    let width = lines.iter().map(|line| line.depth * 3 + line.hex.len()).max().unwrap_or(0).min(40);
+    let mut out = String::new();
+    for line in lines {
+        let indented = format!("{}{}", "   ".repeat(line.depth), line.hex);
+        out.push_str(&format!("{:width$} # {}\n", indented, line.comment, width = width));
+    }
+    out
+}
+
+struct Line {
+    depth: usize,
+    hex: String,
+    comment: String,
+}
+
+/// An array, map, indefinite-length string or tag whose items are being annotated.
+enum Nested {
+    /// Items left to read, or None for indefinite length. Maps hold two per entry.
+    Items { map: bool, remaining: Option<u128>, items: u128 },
+    /// The chunks of an indefinite-length string of this major type.
+    Chunks(u8),
+    Tag,
+}
+
+/// Adds a line per head or chunk to `lines`. On failure, returns where the failing item starts,
+/// how deeply it is nested and the error, with its offset from the start of `bytes`.
+fn annotate_items(bytes: &[u8], lines: &mut Vec<Line>) -> Result<(), (usize, usize, CborError)> {
+    let mut open: Vec<Nested> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() || !open.is_empty() {
+        let start = i;
+        let depth = open.len();
+        let fail = |error: CborError| (start, depth, error.at(start));
+        let ill_formed = |message: String| (start, depth, CborError::new(CborErrorKind::IllFormed(message)).at(start));
+        let initial_byte = byte_at(bytes, i).map_err(|e| (start, depth, e))?;
+        let mut line = |len: usize, comment: String| {
+            lines.push(Line { depth, hex: to_hex(&bytes[start..start + len]), comment });
+        };
+        if let Some(Nested::Chunks(major_type)) = open.last() {
+            if initial_byte != 0xff && (initial_byte >> 5 != *major_type || initial_byte & 0x1f == 0x1f) {
+                return Err(ill_formed(format!(
+                    "Byte {:x} is not a valid chunk of an indefinite-length string of major type {}", initial_byte, major_type
+                )))
+            }
+        }
+        let major_type = initial_byte >> 5;
+        let argument = || read_argument(&bytes[start..]).map_err(fail);
+        match expected_data_item(initial_byte) {
+            DataItem::Stop => {
+                match open.pop() {
+                    Some(Nested::Items { map: true, remaining: None, items }) if !items.is_multiple_of(2) => {
+                        return Err(ill_formed("Indefinite-length map ends after a key without a value".to_owned()))
+                    },
+                    Some(Nested::Items { remaining: None, .. } | Nested::Chunks(_)) => {},
+                    _ => return Err(ill_formed("\"break\" outside of an indefinite length item".to_owned())),
+                }
+                lines.push(Line { depth: depth - 1, hex: "ff".to_owned(), comment: "break".to_owned() });
+                i += 1;
+            },
+            DataItem::TerminatedByteString | DataItem::TerminatedTextString => {
+                line(1, format!("{}(*)", if major_type == 2 { "bytes" } else { "text" }));
+                open.push(Nested::Chunks(major_type));
+                i += 1;
+                continue
+            },
+            DataItem::TerminatedArray | DataItem::TerminatedMap => {
+                line(1, format!("{}(*)", if major_type == 4 { "array" } else { "map" }));
+                open.push(Nested::Items { map: major_type == 5, remaining: None, items: 0 });
+                i += 1;
+                continue
+            },
+            DataItem::SmallInt(_) | DataItem::Uint1 | DataItem::Uint2 | DataItem::Uint4 | DataItem::Uint8 => {
+                let (argument, head_len) = argument()?;
+                line(head_len, format!("unsigned({})", argument));
+                i += head_len;
+            },
+            DataItem::SmallNegInt(_) | DataItem::NegUint1 | DataItem::NegUint2 | DataItem::NegUint4 | DataItem::NegUint8 => {
+                let (argument, head_len) = argument()?;
+                line(head_len, format!("negative({})", -1 - argument as i128));
+                i += head_len;
+            },
+            DataItem::SmallByteString(_) | DataItem::ByteString1 | DataItem::ByteString2 | DataItem::ByteString4 | DataItem::ByteString8
+            | DataItem::SmallTextString(_) | DataItem::TextString1 | DataItem::TextString2 | DataItem::TextString4 | DataItem::TextString8 => {
+                let (argument, head_len) = argument()?;
+                let content = usize::try_from(argument).ok()
+                    .and_then(|len| bytes[start + head_len..].get(..len))
+                    .ok_or_else(|| fail(CborError::new(CborErrorKind::UnexpectedEnd {
+                        needed: (argument as usize).saturating_sub(bytes.len() - start - head_len),
+                    }).at(bytes.len() - start)))?;
+                let comment = match major_type {
+                    2 => format!("bytes({})", content.len()),
+                    _ => {
+                        let text = std::str::from_utf8(content)
+                            .map_err(|e| fail(CborError::new(CborErrorKind::InvalidUtf8).at(head_len + e.valid_up_to())))?;
+                        let mut comment = format!("text({}) ", content.len());
+                        write_quoted(&mut comment, text);
+                        comment
+                    },
+                };
+                line(head_len + content.len(), comment);
+                i += head_len + content.len();
+            },
+            DataItem::SmallArray(_) | DataItem::Array1 | DataItem::Array2 | DataItem::Array4 | DataItem::Array8
+            | DataItem::SmallMap(_) | DataItem::Map1 | DataItem::Map2 | DataItem::Map4 | DataItem::Map8 => {
+                let (len, head_len) = argument()?;
+                line(head_len, format!("{}({})", if major_type == 4 { "array" } else { "map" }, len));
+                i += head_len;
+                if len > 0 {
+                    let remaining = if major_type == 5 { len as u128 * 2 } else { len as u128 };
+                    open.push(Nested::Items { map: major_type == 5, remaining: Some(remaining), items: 0 });
+                    continue
+                }
+            },
+            DataItem::Tag(_) | DataItem::UnsignedBigNum | DataItem::NegativeBigNum
+            | DataItem::Tag1 | DataItem::Tag2 | DataItem::Tag4 | DataItem::Tag8 => {
+                let (tag, head_len) = argument()?;
+                line(head_len, format!("tag({})", tag));
+                open.push(Nested::Tag);
+                i += head_len;
+                continue
+            },
+            DataItem::Bool(_) | DataItem::Null | DataItem::Undefined | DataItem::NotSupported
+            | DataItem::Float2 | DataItem::Float4 | DataItem::Float8 => {
+                let (comment, len) = match initial_byte {
+                    0xf4 => ("false".to_owned(), 1),
+                    0xf5 => ("true".to_owned(), 1),
+                    0xf6 => ("null".to_owned(), 1),
+                    0xf7 => ("undefined".to_owned(), 1),
+                    0xf8 => match byte_at(bytes, start + 1).map_err(|e| (start, depth, e))? {
+                        simple @ 0x20.. => (format!("simple({})", simple), 2),
+                        simple => return Err(ill_formed(format!("Simple value {} must be encoded in a single byte", simple))),
+                    },
+                    0xf9 => {
+                        let half = array_at(bytes, start + 1).map_err(|e| (start, depth, e))?;
+                        (format!("float16({})", float_text(f16_to_f64(u16::from_be_bytes(half)), true)), 3)
+                    },
+                    0xfa => {
+                        let single = array_at(bytes, start + 1).map_err(|e| (start, depth, e))?;
+                        (format!("float32({})", float_text(f32::from_be_bytes(single) as f64, true)), 5)
+                    },
+                    0xfb => {
+                        let double = array_at(bytes, start + 1).map_err(|e| (start, depth, e))?;
+                        (format!("float64({})", float_text(f64::from_be_bytes(double), false)), 9)
+                    },
+                    _ => (format!("simple({})", initial_byte & 0x1f), 1),
+                };
+                line(len, comment);
+                i += len;
+            },
+            DataItem::SimpleOrFloat | DataItem::InvalidByte => {
+                return Err(ill_formed(format!("Byte {:x} is not a valid initial byte", initial_byte)))
+            },
+        }
+        // A whole item has been annotated, which may complete the items it is in
+        loop {
+            match open.last_mut() {
+                None => break,
+                Some(Nested::Tag) => {
+                    open.pop();
+                },
+                Some(Nested::Items { remaining: Some(remaining), items, .. }) if *items + 1 == *remaining => {
+                    open.pop();
+                },
+                Some(Nested::Items { items, .. }) => {
+                    *items += 1;
+                    break
+                },
+                Some(Nested::Chunks(_)) => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cbor::{Cbor, Value};
+
+    use super::*;
+
+    #[test]
+    fn test_diagnostic() {
+        let value = Value::Map(vec![
+            (Value::Text("a".to_owned()), Value::Array(vec![Value::Unsigned(1), Value::Negative(1), Value::Bytes(vec![0x00, 0xff])])),
+            (Value::Unsigned(1), Value::Tag(1, Box::new(Value::Unsigned(1_700_000_000)))),
+        ]);
+        assert_eq!(to_diagnostic(&value.to_cbor_bytes()).unwrap(), "{\"a\": [1, -2, h'00ff'], 1: 1(1700000000)}");
+        assert_eq!(
+            to_diagnostic_pretty(&value.to_cbor_bytes()).unwrap(),
+            "{\n  \"a\": [\n    1,\n    -2,\n    h'00ff'\n  ],\n  1: 1(1700000000)\n}"
+        );
+
+        let cases: [(&[u8], &str); 29] = [
+            (&[0x00], "0"),
+            (&[0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], "-18446744073709551616"),
+            (&[0x18, 0x01], "1_0"),
+            (&[0x1a, 0x00, 0x00, 0x01, 0x00], "256_2"),
+            (&[0x40], "h''"),
+            (&[0x62, b'h', b'i'], "\"hi\""),
+            (&[0x64, b'"', b'\\', b'\n', 0x01], "\"\\\"\\\\\\n\\u0001\""),
+            (&[0x62, 0xc3, 0xa9], "\"\u{e9}\""),
+            (&[0x78, 0x01, b'a'], "\"a\"_0"),
+            (&[0x5f, 0x42, 0x01, 0x02, 0x41, 0x03, 0xff], "(_ h'0102', h'03')"),
+            (&[0x7f, 0x61, b'a', 0x78, 0x01, b'b', 0xff], "(_ \"a\", \"b\"_0)"),
+            (&[0x5f, 0xff], "''_"),
+            (&[0x7f, 0xff], "\"\"_"),
+            (&[0x80], "[]"),
+            (&[0xa0], "{}"),
+            (&[0x98, 0x00], "[_0 ]"),
+            (&[0x98, 0x01, 0x01], "[_0 1]"),
+            (&[0x9f, 0xff], "[_ ]"),
+            (&[0x9f, 0x01, 0x9f, 0xff, 0x80, 0xff], "[_ 1, [_ ], []]"),
+            (&[0xbf, 0x61, b'a', 0x01, 0xff], "{_ \"a\": 1}"),
+            (&[0xd8, 0x20, 0x60], "32(\"\")"),
+            (&[0xd8, 0x01, 0x00], "1_0(0)"),
+            (&[0xc6, 0xc6, 0x80], "6(6([]))"),
+            (&[0xf7], "undefined"),
+            (&[0xf0], "simple(16)"),
+            (&[0xf8, 0xff], "simple(255)"),
+            (&[0xf9, 0x3e, 0x00], "1.5_1"),
+            (&[0xfa, 0x3d, 0xcc, 0xcc, 0xcd], "0.1_2"),
+            (&[0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a], "0.1_3"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(to_diagnostic(bytes).unwrap(), expected, "{:x?}", bytes);
+        }
+        assert_eq!(to_diagnostic(&[0x83, 0xf4, 0xf5, 0xf6]).unwrap(), "[false, true, null]");
+        assert_eq!(to_diagnostic(&[0xf9, 0x7c, 0x00]).unwrap(), "Infinity_1");
+        assert_eq!(to_diagnostic(&[0xfa, 0xff, 0x80, 0x00, 0x00]).unwrap(), "-Infinity_2");
+        assert_eq!(to_diagnostic(&[0xf9, 0x7e, 0x00]).unwrap(), "NaN_1");
+        assert_eq!(to_diagnostic(&[0xfb, 0x41, 0x0e, 0x84, 0x80, 0x00, 0x00, 0x00, 0x00]).unwrap(), "250000.0_3");
+
+        // Pretty printing
+        assert_eq!(to_diagnostic_pretty(&[0x80]).unwrap(), "[]");
+        assert_eq!(to_diagnostic_pretty(&[0x9f, 0xff]).unwrap(), "[_ ]");
+        assert_eq!(to_diagnostic_pretty(&[0x9f, 0x01, 0xa0, 0xff]).unwrap(), "[_\n  1,\n  {}\n]");
+        assert_eq!(to_diagnostic_pretty(&[0xc1, 0x81, 0xbf, 0x01, 0x02, 0xff]).unwrap(), "1([\n  {_\n    1: 2\n  }\n])");
+
+        // A hundred thousand nested arrays
+        let mut nested = vec![0x81; 100_000];
+        nested.push(0x00);
+        let diagnostic = to_diagnostic(&nested).unwrap();
+        assert_eq!(diagnostic.len(), 200_001);
+        assert!(diagnostic.starts_with("[[[") && diagnostic.contains("[0]]]") && diagnostic.ends_with("]]]"));
+    }
+
+    #[test]
+    fn test_from_diagnostic() {
+        let round_trips: [&[u8]; 24] = [
+            &[0x00], &[0x20], &[0x18, 0x01], &[0x1b, 0, 0, 0, 0, 0, 0, 0, 0x01],
+            &[0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], &[0x64, b'"', b'\\', b'\n', 0x01],
+            &[0x78, 0x01, b'a'], &[0x5f, 0x42, 0x01, 0x02, 0x41, 0x03, 0xff], &[0x7f, 0x61, b'a', 0x78, 0x01, b'b', 0xff],
+            &[0x5f, 0xff], &[0x7f, 0xff], &[0x98, 0x00], &[0x9f, 0x01, 0x9f, 0xff, 0x80, 0xff],
+            &[0xbf, 0x61, b'a', 0x01, 0xff], &[0xb9, 0x00, 0x01, 0x01, 0x02], &[0xd8, 0x01, 0x00], &[0xc6, 0xc6, 0x80],
+            &[0xf0], &[0xf8, 0xff], &[0xf9, 0x3e, 0x00], &[0xf9, 0x2e, 0x66], &[0xfa, 0x3d, 0xcc, 0xcc, 0xcd],
+            &[0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a], &[0xf9, 0xfc, 0x00],
+        ];
+        for bytes in round_trips {
+            let diagnostic = to_diagnostic(bytes).unwrap();
+            assert_eq!(from_diagnostic(&diagnostic).unwrap(), bytes, "{}", diagnostic);
+            let pretty = to_diagnostic_pretty(bytes).unwrap();
+            assert_eq!(from_diagnostic(&pretty).unwrap(), bytes, "{}", pretty);
+        }
+        let value = Value::Map(vec![
+            (Value::Text("a".to_owned()), Value::Array(vec![Value::Unsigned(1), Value::Negative(1), Value::Bytes(vec![0x00, 0xff])])),
+            (Value::Unsigned(1), Value::Tag(1, Box::new(Value::Unsigned(1_700_000_000)))),
+        ]);
+        assert_eq!(value_from_diagnostic("{\"a\": [1, -2, h'00ff'], 1: 1(1700000000)}").unwrap(), value);
+
+        // Notation the printer doesn't write
+        assert_eq!(from_diagnostic("b64'AP8='").unwrap(), vec![0x42, 0x00, 0xff]);
+        assert_eq!(from_diagnostic("b64'-_8'").unwrap(), vec![0x42, 0xfb, 0xff]);
+        assert_eq!(from_diagnostic("'hi'").unwrap(), vec![0x42, b'h', b'i']);
+        assert_eq!(from_diagnostic("h'00 01\n  02 /three/ 03'").unwrap(), vec![0x44, 0, 1, 2, 3]);
+        assert_eq!(from_diagnostic("h'01 /it's/ 02'").unwrap(), vec![0x42, 1, 2]);
+        assert_eq!(from_diagnostic("\"\\u00e9\\ud83d\\ude00\"").unwrap(), "\u{e9}\u{1f600}".to_owned().to_cbor_bytes());
+        assert_eq!(from_diagnostic("[0x10, -0x10, 0o10, 0b10, -0]").unwrap(), vec![0x85, 0x10, 0x2f, 0x08, 0x02, 0x00]);
+        assert_eq!(from_diagnostic("[1.5, 0.1, 100000.0, 1e300, NaN, -Infinity]").unwrap(), vec![
+            0x86, 0xf9, 0x3e, 0x00, 0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a, 0xfa, 0x47, 0xc3, 0x50, 0x00,
+            0xfb, 0x7e, 0x37, 0xe4, 0x3c, 0x88, 0x00, 0x75, 0x9c, 0xf9, 0x7e, 0x00, 0xf9, 0xfc, 0x00,
+        ]);
+        assert_eq!(from_diagnostic("0.1_1").unwrap(), vec![0xf9, 0x2e, 0x66]);
+        assert_eq!(from_diagnostic("1.0_3").unwrap(), vec![0xfb, 0x3f, 0xf0, 0, 0, 0, 0, 0, 0]);
+        assert!(from_diagnostic("[1, 2]_0").is_err());
+        assert_eq!(from_diagnostic("{_1 }").unwrap(), vec![0xb9, 0x00, 0x00]);
+        assert_eq!(from_diagnostic("[false, true, null, undefined, simple(0), simple(19)]").unwrap(), vec![0x86, 0xf4, 0xf5, 0xf6, 0xf7, 0xe0, 0xf3]);
+        assert_eq!(from_diagnostic("# a comment\n[1, # another\n 2] / and another /").unwrap(), vec![0x82, 0x01, 0x02]);
+        assert_eq!(from_diagnostic("24(h'f6')").unwrap(), vec![0xd8, 0x18, 0x41, 0xf6]);
+
+        // A hundred thousand nested arrays
+        let mut nested = vec![0x81; 100_000];
+        nested.push(0x00);
+        assert_eq!(from_diagnostic(&to_diagnostic(&nested).unwrap()).unwrap(), nested);
+    }
+
+    #[test]
+    fn test_from_diagnostic_errors() {
+        let invalid = [
+            ("", 0), ("[1, 2", 5), ("[1 2]", 3), ("{1}", 2), ("{1: 2, 3}", 8), ("1 2", 2), ("h'0'", 2), ("h'0g'", 3), ("h'00", 1), ("h'00 /'/", 1),
+            ("b64'A'", 4), ("\"abc", 0), ("\"\\x\"", 1), ("18446744073709551616", 0), ("-18446744073709551617", 0),
+            ("-1(0)", 0), ("1_4", 1), ("256_0", 0), ("1.5_0", 0), ("1e6_1", 0), ("simple(24)", 7), ("(_ )", 3), ("(_ 1)", 3), ("(_ h'00', \"a\")", 10),
+            ("\"a\"_", 0), ("/ comment", 0), ("nope", 0), ("--1", 0),
+        ];
+        for (text, offset) in invalid {
+            let error = from_diagnostic(text).unwrap_err();
+            assert!(matches!(error.kind, CborErrorKind::InvalidDiagnostic(_)), "{}: {:?}", text, error);
+            assert_eq!(error.offset, offset, "{}: {}", text, error);
+        }
+        let too_long = format!("[_0 {}]", (0..256).map(|n| n.to_string()).collect::<Vec<_>>().join(", "));
+        assert_eq!(from_diagnostic(&too_long).unwrap_err().offset, 0);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let bytes = [0x83, 0x01, 0x65, b'h', b'e', b'l', b'l', b'o', 0xc1, 0x02];
+        assert_eq!(annotate(&bytes), [
+            "83              # array(3)",
+            "   01           # unsigned(1)",
+            "   6568656c6c6f # text(5) \"hello\"",
+            "   c1           # tag(1)",
+            "      02        # unsigned(2)",
+            "",
+        ].join("\n"));
+        assert_eq!(annotate(&[0xbf, 0x5f, 0x41, 0x01, 0xff, 0x9f, 0xff, 0xff, 0x38, 0x63, 0xf8, 0x20, 0xf9, 0x3e, 0x00, 0xf6]), [
+            "bf         # map(*)",
+            "   5f      # bytes(*)",
+            "      4101 # bytes(1)",
+            "   ff      # break",
+            "   9f      # array(*)",
+            "   ff      # break",
+            "ff         # break",
+            "3863       # negative(-100)",
+            "f820       # simple(32)",
+            "f93e00     # float16(1.5)",
+            "f6         # null",
+            "",
+        ].join("\n"));
+        assert_eq!(annotate(&[]), "");
+
+        // Malformed input is annotated up to the failure
+        assert_eq!(annotate(&[0x82, 0x01, 0x62, 0xc3, 0x28]), [
+            "82        # array(2)",
+            "   01     # unsigned(1)",
+            "   62[c3] # error: text string is not valid utf-8 at byte 3",
+            "",
+        ].join("\n"));
+        assert_eq!(annotate(&[0xa1, 0x01, 0x02, 0xff]), [
+            "a1    # map(1)",
+            "   01 # unsigned(1)",
+            "   02 # unsigned(2)",
+            "[ff]  # error: \"break\" outside of an indefinite length item at byte 3",
+            "",
+        ].join("\n"));
+        assert_eq!(annotate(&[0x82, 0x61, b'a']), [
+            "82      # array(2)",
+            "   6161 # text(1) \"a\"",
+            "        # error: unexpected end of input, 1 more byte(s) needed at byte 3",
+            "",
+        ].join("\n"));
+        assert!(annotate(&[0x81, 0x1c]).ends_with("   [1c] # error: Byte 1c is not a valid initial byte at byte 1\n"));
+        assert!(annotate(&[0x5f, 0x61, b'a', 0xff]).ends_with("   [61] # error: Byte 61 is not a valid chunk of an indefinite-length string of major type 2 at byte 1\n"));
+    }
+
+    #[test]
+    fn test_diagnostic_errors() {
+        let ill_formed: [&[u8]; 14] = [
+            &[], &[0x18], &[0x62, b'a'], &[0x82, 0x00], &[0x5f, 0x41, 0x00], &[0xf9, 0x00],
+            &[0x1c], &[0xfc], &[0x1f], &[0xdf], &[0xff], &[0x81, 0xff], &[0x5f, 0x5f, 0xff, 0xff], &[0xf8, 0x00],
+        ];
+        for bytes in ill_formed {
+            assert!(to_diagnostic(bytes).is_err(), "{:x?}", bytes);
+        }
+        let error = to_diagnostic(&[0xbf, 0x01, 0xff]).unwrap_err();
+        assert!(matches!(error.kind, CborErrorKind::IllFormed(_)));
+        assert_eq!(error.offset, 2);
+        let error = to_diagnostic(&[0x82, 0x00, 0x62, b'a', 0xff]).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::InvalidUtf8);
+        assert_eq!(error.offset, 4);
+        let error = to_diagnostic(&[0x01, 0x02]).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::TrailingBytes(1));
+        assert_eq!(error.offset, 1);
+    }
+}
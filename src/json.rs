@@ -0,0 +1,553 @@
+use crate::{
+    base64::encode_base64,
+    cbor::{decode_cbor_exact, Cbor, CborError, CborErrorKind, DecodeOptions, EncodeOptions, FloatEncoding, FloatWidth, Value},
+    diag::write_quoted,
+};
+
+/// Options for `to_json_with`. The default follows the advice of RFC 8949 section 6.1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonOptions {
+    pub tags: JsonTags,
+    pub bignums: JsonBignums,
+}
+
+/// How tags other than bignums (2 and 3) and expected conversions (21 to 23) are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonTags {
+    /// Only the tag content, so the tag number is lost.
+    #[default]
+    Content,
+    /// An object `{"tag": number, "value": content}`.
+    Object,
+}
+
+/// How bignums (tags 2 and 3 on a byte string) are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JsonBignums {
+    /// A base64url string of the magnitude, after a `~` for negative bignums.
+    #[default]
+    Base64url,
+    /// A string of decimal digits, like "-18446744073709551617".
+    DecimalString,
+    /// A number. JSON allows any size, but many parsers round large numbers to a float.
+    Number,
+}
+
+/// The encoding byte strings get, which expected conversion tags 21 to 23 change for everything inside them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BytesEncoding {
+    Base64url,
+    Base64,
+    Base16,
+}
+
+/// Converts a value to JSON text following RFC 8949 section 6.1.
+///
+/// Byte strings become base64url strings without padding, or base64 or uppercase hex inside
+/// tags 21 to 23. Map keys that aren't text are written as strings holding their JSON, so the
+/// key 1 becomes "1". Undefined, other simple values, NaN and the infinities become null.
+pub fn to_json(value: &Value) -> String {
+    to_json_with(value, &JsonOptions::default())
+}
+
+/// Converts a value to JSON text, writing tags and bignums as `options` say.
+pub fn to_json_with(value: &Value, options: &JsonOptions) -> String {
+    let mut out = String::new();
+    write_json(&mut out, value, options, BytesEncoding::Base64url);
+    out
+}
+
+/// Converts the data item in `bytes` to JSON text. Nothing may follow it.
+pub fn cbor_to_json(bytes: &[u8], options: &JsonOptions) -> Result<String, CborError> {
+    Ok(to_json_with(&decode_cbor_exact(bytes)?, options))
+}
+
+/// Converts JSON text to CBOR, with floats in the shortest width that holds them exactly
+/// (RFC 8949 section 6.2).
+pub fn json_to_cbor(text: &str) -> Result<Vec<u8>, CborError> {
+    Ok(from_json(text)?.to_cbor_bytes_with(&EncodeOptions { float_encoding: FloatEncoding::Shortest, ..Default::default() }))
+}
+
+fn write_json(out: &mut String, value: &Value, options: &JsonOptions, bytes_encoding: BytesEncoding) {
+    match value {
+        Value::Unsigned(num) => out.push_str(&num.to_string()),
+        Value::Negative(num) => out.push_str(&(-1 - *num as i128).to_string()),
+        Value::Bytes(content) => {
+            out.push('"');
+            match bytes_encoding {
+                BytesEncoding::Base64url => out.push_str(&encode_base64(content, true)),
+                BytesEncoding::Base64 => out.push_str(&encode_base64(content, false)),
+                BytesEncoding::Base16 => content.iter().for_each(|byte| out.push_str(&format!("{:02X}", byte))),
+            }
+            out.push('"');
+        },
+        Value::Text(text) => write_quoted(out, text),
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_json(out, item, options, bytes_encoding);
+            }
+            out.push(']');
+        },
+        Value::Map(entries) => {
+            out.push('{');
+            for (index, (key, value)) in entries.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                match key {
+                    Value::Text(text) => write_quoted(out, text),
+                    _ => {
+                        let mut key_json = String::new();
+                        write_json(&mut key_json, key, options, bytes_encoding);
+                        match key_json.starts_with('"') {
+                            true => out.push_str(&key_json),
+                            false => write_quoted(out, &key_json),
+                        }
+                    },
+                }
+                out.push(':');
+                write_json(out, value, options, bytes_encoding);
+            }
+            out.push('}');
+        },
+        Value::Tag(tag @ (2 | 3), content) if matches!(**content, Value::Bytes(_)) => {
+            let Value::Bytes(magnitude) = &**content else { unreachable!() };
+            let negative = *tag == 3;
+            match options.bignums {
+                JsonBignums::Base64url => {
+                    out.push('"');
+                    if negative {
+                        out.push('~');
+                    }
+                    out.push_str(&encode_base64(magnitude, true));
+                    out.push('"');
+                },
+                JsonBignums::DecimalString => {
+                    out.push('"');
+                    out.push_str(&bignum_to_decimal(magnitude, negative));
+                    out.push('"');
+                },
+                JsonBignums::Number => out.push_str(&bignum_to_decimal(magnitude, negative)),
+            }
+        },
+        Value::Tag(21, content) => write_json(out, content, options, BytesEncoding::Base64url),
+        Value::Tag(22, content) => write_json(out, content, options, BytesEncoding::Base64),
+        Value::Tag(23, content) => write_json(out, content, options, BytesEncoding::Base16),
+        Value::Tag(tag, content) => match options.tags {
+            JsonTags::Content => write_json(out, content, options, bytes_encoding),
+            JsonTags::Object => {
+                out.push_str(&format!("{{\"tag\":{},\"value\":", tag));
+                write_json(out, content, options, bytes_encoding);
+                out.push('}');
+            },
+        },
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Float(float, _) if float.is_finite() => out.push_str(&format!("{:?}", float)),
+        Value::Null | Value::Undefined | Value::Simple(_) | Value::Float(..) => out.push_str("null"),
+    }
+}
+
+/// Writes the bignum with big-endian `magnitude` in decimal. A negative bignum is -1 - magnitude.
+fn bignum_to_decimal(magnitude: &[u8], negative: bool) -> String {
+    // Little-endian limbs of 9 decimal digits each
+    let mut limbs: Vec<u32> = vec![0];
+    for byte in magnitude {
+        let mut carry = *byte as u64;
+        for limb in limbs.iter_mut() {
+            let product = *limb as u64 * 256 + carry;
+            *limb = (product % 1_000_000_000) as u32;
+            carry = product / 1_000_000_000;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+    }
+    if negative {
+        let mut i = 0;
+        loop {
+            if i == limbs.len() {
+                limbs.push(0);
+            }
+            limbs[i] += 1;
+            if limbs[i] < 1_000_000_000 {
+                break
+            }
+            limbs[i] = 0;
+            i += 1;
+        }
+    }
+    let mut digits = if negative { "-".to_owned() } else { String::new() };
+    let mut limbs = limbs.iter().rev();
+    digits.push_str(&limbs.next().unwrap_or(&0).to_string());
+    for limb in limbs {
+        digits.push_str(&format!("{:09}", limb));
+    }
+    digits
+}
+
+/// The big-endian magnitude of a non-negative integer written in decimal digits.
+fn decimal_to_magnitude(digits: &str) -> Vec<u8> {
+    let mut magnitude: Vec<u8> = Vec::new();
+    for digit in digits.bytes() {
+        let mut carry = (digit - b'0') as u32;
+        for byte in magnitude.iter_mut().rev() {
+            let product = *byte as u32 * 10 + carry;
+            *byte = product as u8;
+            carry = product >> 8;
+        }
+        if carry > 0 {
+            magnitude.insert(0, carry as u8);
+        }
+    }
+    magnitude
+}
+
+/// Subtracts one from a non-zero big-endian magnitude and drops leading zero bytes.
+fn decrement(mut magnitude: Vec<u8>) -> Vec<u8> {
+    for byte in magnitude.iter_mut().rev() {
+        if *byte > 0 {
+            *byte -= 1;
+            break
+        }
+        *byte = 0xff;
+    }
+    let leading_zeros = magnitude.iter().take_while(|byte| **byte == 0).count();
+    magnitude.split_off(leading_zeros)
+}
+
+/// Parses JSON text into a value (RFC 8949 section 6.2). Numbers without a fraction or exponent
+/// become integers, or bignums if they don't fit in 64 bits, and all other numbers become floats.
+/// Arrays and objects may be nested as deeply as `DecodeOptions::default().max_depth`.
+/// Error offsets are in bytes of `text`.
+pub fn from_json(text: &str) -> Result<Value, CborError> {
+    let mut parser = JsonParser { text: text.as_bytes(), pos: 0, depth: 0 };
+    let value = parser.value()?;
+    parser.skip_space();
+    if parser.pos < text.len() {
+        return Err(parser.error("Unexpected text after the JSON value"))
+    }
+    Ok(value)
+}
+
+struct JsonParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn value(&mut self) -> Result<Value, CborError> {
+        self.skip_space();
+        match self.text.get(self.pos) {
+            Some(b'{') => self.nested(|parser| {
+                let mut entries = Vec::new();
+                parser.pos += 1;
+                parser.items(b'}', |parser| {
+                    parser.skip_space();
+                    if parser.text.get(parser.pos) != Some(&b'"') {
+                        return Err(parser.error("Expected a string key"))
+                    }
+                    let key = parser.string()?;
+                    parser.skip_space();
+                    parser.expect(b':')?;
+                    entries.push((Value::Text(key), parser.value()?));
+                    Ok(())
+                })?;
+                Ok(Value::Map(entries))
+            }),
+            Some(b'[') => self.nested(|parser| {
+                let mut items = Vec::new();
+                parser.pos += 1;
+                parser.items(b']', |parser| {
+                    items.push(parser.value()?);
+                    Ok(())
+                })?;
+                Ok(Value::Array(items))
+            }),
+            Some(b'"') => Ok(Value::Text(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => {
+                for (literal, value) in [("true", Value::Bool(true)), ("false", Value::Bool(false)), ("null", Value::Null)] {
+                    if self.text[self.pos..].starts_with(literal.as_bytes()) {
+                        self.pos += literal.len();
+                        return Ok(value)
+                    }
+                }
+                Err(self.error("Expected a JSON value"))
+            },
+            None => Err(self.error("Expected a JSON value")),
+        }
+    }
+
+    fn nested<F>(&mut self, parse: F) -> Result<Value, CborError>
+    where
+        F: FnOnce(&mut Self) -> Result<Value, CborError>,
+    {
+        let max_depth = DecodeOptions::default().max_depth;
+        if self.depth >= max_depth {
+            return Err(CborError::new(CborErrorKind::DepthExceeded(max_depth)).at(self.pos))
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    /// Parses comma separated items with `item` up to and including `close`.
+    fn items<F>(&mut self, close: u8, mut item: F) -> Result<(), CborError>
+    where
+        F: FnMut(&mut Self) -> Result<(), CborError>,
+    {
+        self.skip_space();
+        if self.text.get(self.pos) == Some(&close) {
+            self.pos += 1;
+            return Ok(())
+        }
+        loop {
+            item(self)?;
+            self.skip_space();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(c) if *c == close => {
+                    self.pos += 1;
+                    return Ok(())
+                },
+                _ => return Err(self.error(if close == b']' { "Expected ',' or ']'" } else { "Expected ',' or '}'" })),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, CborError> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let digits_start = parser.pos;
+            while parser.text.get(parser.pos).is_some_and(u8::is_ascii_digit) {
+                parser.pos += 1;
+            }
+            parser.pos > digits_start
+        };
+        if self.text[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        let integer_start = self.pos;
+        if !digits(self) || (self.text[integer_start] == b'0' && self.pos - integer_start > 1) {
+            return Err(CborError::new(CborErrorKind::InvalidJson("Invalid number".to_owned())).at(start))
+        }
+        let mut integer = true;
+        if self.text.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            integer = false;
+            if !digits(self) {
+                return Err(self.error("Expected digits after '.'"))
+            }
+        }
+        if let Some(b'e' | b'E') = self.text.get(self.pos) {
+            self.pos += 1;
+            integer = false;
+            if let Some(b'+' | b'-') = self.text.get(self.pos) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("Expected digits in the exponent"))
+            }
+        }
+        // The number is ASCII, so it is valid utf-8
+        let number = std::str::from_utf8(&self.text[start..self.pos]).unwrap_or_default();
+        if integer {
+            return Ok(match number.parse::<i128>() {
+                Ok(num @ 0..=0xffff_ffff_ffff_ffff) => Value::Unsigned(num as u64),
+                Ok(num @ -0x1_0000_0000_0000_0000..0) => Value::Negative((-1 - num) as u64),
+                _ => match number.strip_prefix('-') {
+                    Some(digits) => Value::Tag(3, Box::new(Value::Bytes(decrement(decimal_to_magnitude(digits))))),
+                    None => Value::Tag(2, Box::new(Value::Bytes(decimal_to_magnitude(number)))),
+                },
+            })
+        }
+        number.parse::<f64>().map(|float| Value::Float(float, FloatWidth::Double))
+            .map_err(|_| CborError::new(CborErrorKind::InvalidJson("Invalid number".to_owned())).at(start))
+    }
+
+    fn string(&mut self) -> Result<String, CborError> {
+        let mut content = Vec::new();
+        self.pos += 1;
+        loop {
+            match self.text.get(self.pos) {
+                None => return Err(self.error("Unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    // The input is a str and escapes produce whole chars, so this is valid utf-8
+                    return Ok(String::from_utf8(content).unwrap_or_default())
+                },
+                Some(b'\\') => {
+                    let escape_start = self.pos;
+                    let invalid = || CborError::new(CborErrorKind::InvalidJson("Invalid escape sequence".to_owned())).at(escape_start);
+                    let escaped = match self.text.get(self.pos + 1) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{08}',
+                        Some(b'f') => '\u{0c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let high = self.code_unit(self.pos + 2).ok_or_else(invalid)?;
+                            self.pos += 4;
+                            let mut units = vec![high];
+                            if (0xd800..0xdc00).contains(&high) {
+                                if !self.text[self.pos + 2..].starts_with(b"\\u") {
+                                    return Err(invalid())
+                                }
+                                units.push(self.code_unit(self.pos + 4).ok_or_else(invalid)?);
+                                self.pos += 6;
+                            }
+                            char::decode_utf16(units).next().and_then(|c| c.ok()).ok_or_else(invalid)?
+                        },
+                        _ => return Err(invalid()),
+                    };
+                    let mut buffer = [0; 4];
+                    content.extend_from_slice(escaped.encode_utf8(&mut buffer).as_bytes());
+                    self.pos += 2;
+                },
+                Some(0x00..0x20) => return Err(self.error("Control characters in strings must be escaped")),
+                Some(byte) => {
+                    content.push(*byte);
+                    self.pos += 1;
+                },
+            }
+        }
+    }
+
+    /// Reads the four hex digits of a `\u` escape starting at `start`.
+    fn code_unit(&self, start: usize) -> Option<u16> {
+        let hex = std::str::from_utf8(self.text.get(start..start + 4)?).ok()?;
+        match hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+            true => u16::from_str_radix(hex, 16).ok(),
+            false => None,
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.text.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), CborError> {
+        match self.text.get(self.pos) {
+            Some(c) if *c == expected => {
+                self.pos += 1;
+                Ok(())
+            },
+            _ => Err(self.error(&format!("Expected {:?}", expected as char))),
+        }
+    }
+
+    fn error(&self, message: &str) -> CborError {
+        CborError::new(CborErrorKind::InvalidJson(message.to_owned())).at(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let value = Value::Map(vec![
+            (Value::Text("a".to_owned()), Value::Array(vec![Value::Unsigned(1), Value::Negative(1), Value::Float(1.5, FloatWidth::Double)])),
+            (Value::Text("b\n".to_owned()), Value::Map(vec![])),
+            (Value::Text("c".to_owned()), Value::Array(vec![Value::Bool(true), Value::Bool(false), Value::Null])),
+        ]);
+        let json = "{\"a\":[1,-2,1.5],\"b\\n\":{},\"c\":[true,false,null]}";
+        assert_eq!(to_json(&value), json);
+        assert_eq!(from_json(json).unwrap(), value);
+        assert_eq!(from_json(" [ 1 , {\"x\" : \"\\u00e9\\ud83d\\ude00\\/\" } ] ").unwrap(), Value::Array(vec![
+            Value::Unsigned(1),
+            Value::Map(vec![(Value::Text("x".to_owned()), Value::Text("\u{e9}\u{1f600}/".to_owned()))]),
+        ]));
+
+        // CBOR that JSON has no direct form for
+        assert_eq!(to_json(&Value::Bytes(vec![0xfb, 0xff])), "\"-_8\"");
+        assert_eq!(to_json(&Value::Map(vec![(Value::Unsigned(1), Value::Undefined)])), "{\"1\":null}");
+        assert_eq!(to_json(&Value::Map(vec![(Value::Bytes(vec![1]), Value::Simple(16))])), "{\"AQ\":null}");
+        assert_eq!(to_json(&Value::Map(vec![(Value::Array(vec![Value::Text("a".to_owned())]), Value::Null)])), "{\"[\\\"a\\\"]\":null}");
+        assert_eq!(to_json(&Value::Tag(1, Box::new(Value::Unsigned(1_700_000_000)))), "1700000000");
+        assert_eq!(to_json(&Value::Array(vec![Value::Float(f64::NAN, FloatWidth::Double), Value::Float(f64::INFINITY, FloatWidth::Double), Value::Float(1e300, FloatWidth::Double)])), "[null,null,1e300]");
+
+        // Numbers
+        assert_eq!(from_json("18446744073709551615").unwrap(), Value::Unsigned(u64::MAX));
+        assert_eq!(from_json("-18446744073709551616").unwrap(), Value::Negative(u64::MAX));
+        assert_eq!(from_json("18446744073709551616").unwrap(), Value::Tag(2, Box::new(Value::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0]))));
+        assert_eq!(from_json("-18446744073709551617").unwrap(), Value::Tag(3, Box::new(Value::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0]))));
+        assert_eq!(from_json("1e20").unwrap(), Value::Float(1e20, FloatWidth::Double));
+        assert_eq!(from_json("-0").unwrap(), Value::Unsigned(0));
+        assert_eq!(from_json("1.0").unwrap(), Value::Float(1.0, FloatWidth::Double));
+        assert_eq!(from_json("-2.5e-3").unwrap(), Value::Float(-0.0025, FloatWidth::Double));
+        assert_eq!(from_json("1E2").unwrap(), Value::Float(100.0, FloatWidth::Double));
+    }
+
+    #[test]
+    fn test_json_options() {
+        let bignum = Value::Tag(2, Box::new(Value::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0])));
+        let negative_bignum = Value::Tag(3, Box::new(Value::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0])));
+        let bignums = Value::Array(vec![bignum, negative_bignum, Value::Tag(3, Box::new(Value::Bytes(vec![])))]);
+        assert_eq!(to_json(&bignums), "[\"AQAAAAAAAAAA\",\"~AQAAAAAAAAAA\",\"~\"]");
+        let options = JsonOptions { bignums: JsonBignums::DecimalString, ..Default::default() };
+        assert_eq!(to_json_with(&bignums, &options), "[\"18446744073709551616\",\"-18446744073709551617\",\"-1\"]");
+        let options = JsonOptions { bignums: JsonBignums::Number, ..Default::default() };
+        let json = to_json_with(&bignums, &options);
+        assert_eq!(json, "[18446744073709551616,-18446744073709551617,-1]");
+        assert_eq!(from_json(&json).unwrap(), Value::Array(vec![
+            Value::Tag(2, Box::new(Value::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0]))),
+            Value::Tag(3, Box::new(Value::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0]))),
+            Value::Negative(0),
+        ]));
+        let huge = "123456789012345678901234567890123456789012345678901234567890";
+        assert_eq!(to_json_with(&from_json(huge).unwrap(), &options), huge);
+        assert_eq!(to_json_with(&from_json(&format!("-{}", huge)).unwrap(), &options), format!("-{}", huge));
+
+        // Expected conversions apply to every byte string inside them
+        let bytes = Value::Bytes(vec![0xfb, 0xff]);
+        let expected = Value::Array(vec![
+            Value::Tag(21, Box::new(bytes.clone())),
+            Value::Tag(22, Box::new(Value::Array(vec![bytes.clone(), Value::Tag(23, Box::new(bytes.clone()))]))),
+            Value::Tag(23, Box::new(Value::Map(vec![(bytes.clone(), bytes.clone())]))),
+        ]);
+        assert_eq!(to_json(&expected), "[\"-_8\",[\"+/8=\",\"FBFF\"],{\"FBFF\":\"FBFF\"}]");
+
+        // Other tags
+        let tagged = Value::Tag(1, Box::new(Value::Tag(32, Box::new(Value::Text("http://a".to_owned())))));
+        assert_eq!(to_json(&tagged), "\"http://a\"");
+        let options = JsonOptions { tags: JsonTags::Object, ..Default::default() };
+        assert_eq!(to_json_with(&tagged, &options), "{\"tag\":1,\"value\":{\"tag\":32,\"value\":\"http://a\"}}");
+        assert_eq!(to_json_with(&Value::Tag(2, Box::new(Value::Bytes(vec![1]))), &options), "\"AQ\"");
+        assert_eq!(to_json_with(&Value::Tag(2, Box::new(Value::Unsigned(1))), &options), "{\"tag\":2,\"value\":1}");
+
+        // Conversions straight between CBOR and JSON
+        assert_eq!(json_to_cbor("[1, -1, 1.5, 100000.0, 1.1]").unwrap(), vec![
+            0x85, 0x01, 0x20, 0xf9, 0x3e, 0x00, 0xfa, 0x47, 0xc3, 0x50, 0x00, 0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a,
+        ]);
+        assert_eq!(cbor_to_json(&[0x9f, 0x5f, 0x41, 0xfb, 0x41, 0xff, 0xff, 0xf9, 0x7c, 0x00, 0xff], &JsonOptions::default()).unwrap(), "[\"-_8\",null]");
+        assert_eq!(cbor_to_json(&[0x01, 0x02], &JsonOptions::default()).unwrap_err().kind, CborErrorKind::TrailingBytes(1));
+    }
+
+    #[test]
+    fn test_json_errors() {
+        let invalid = [
+            ("", 0), ("[1, 2", 5), ("[1 2]", 3), ("{1: 2}", 1), ("{\"a\" 2}", 5), ("[1,]", 3), ("01", 0), ("1.", 2), ("1e", 2),
+            ("-", 0), ("\"abc", 4), ("\"\\x\"", 1), ("\"\\ud83d\"", 1), ("\"\n\"", 1), ("nul", 0), ("1 2", 2), ("+1", 0),
+        ];
+        for (text, offset) in invalid {
+            let error = from_json(text).unwrap_err();
+            assert!(matches!(error.kind, CborErrorKind::InvalidJson(_)), "{}: {:?}", text, error);
+            assert_eq!(error.offset, offset, "{}: {}", text, error);
+        }
+        let nested = "[".repeat(100_000);
+        assert_eq!(from_json(&nested).unwrap_err().kind, CborErrorKind::DepthExceeded(128));
+    }
+}
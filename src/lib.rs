@@ -1 +1,14 @@
+#[doc(hidden)]
+pub mod base64;
 pub mod cbor;
+pub mod diag;
+pub mod json;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod stream;
+
+#[cfg(feature = "serde")]
+pub use crate::serde::{from_slice, to_vec};
+
+#[cfg(feature = "derive")]
+pub use ezcbor_derive::{Cbor, CborDecode};
@@ -0,0 +1,292 @@
+use std::{fs, io::{self, Read, Write}, process::ExitCode};
+
+use ezcbor::{
+    base64::{decode_base64, encode_base64},
+    cbor::{check_deterministic, validate, CborError},
+    diag::{annotate, to_diagnostic, to_diagnostic_pretty},
+    json::{cbor_to_json, json_to_cbor, JsonBignums, JsonOptions, JsonTags},
+};
+
+const USAGE: &str = "\
+Usage: ezcbor <command> [options] [file]
+
+Reads the file, or stdin if there is none or it is -, and writes to stdout.
+
+Commands:
+  diag        Print the data item in diagnostic notation
+  annotate    Print an annotated hex dump of the data items
+  validate    Check that the input is one well-formed data item
+  to-json     Convert the data item to JSON
+  from-json   Convert JSON to a data item
+
+Options:
+  -i, --input <raw|hex|base64>   How the CBOR input is written (default raw)
+  -o, --output <raw|hex|base64>  How from-json writes its CBOR output (default raw)
+  -p, --pretty                   Indent diag output
+  -d, --deterministic            Make validate also check for core deterministic encoding
+      --tags <content|object>    How to-json writes tags: only their content (default),
+                                 or as {\"tag\": number, \"value\": content}
+      --bignums <base64url|decimal-string|number>
+                                 How to-json writes bignums (default base64url)
+  -h, --help                     Print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Diag,
+    Annotate,
+    Validate,
+    ToJson,
+    FromJson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Raw,
+    Hex,
+    Base64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Options {
+    command: Command,
+    input: Format,
+    output: Format,
+    pretty: bool,
+    deterministic: bool,
+    json: JsonOptions,
+    file: Option<String>,
+}
+
+#[derive(Debug)]
+enum Failure {
+    /// The command line is wrong. Exits with status 2.
+    Usage(String),
+    /// The input is invalid or couldn't be read. Exits with status 1.
+    Input(String),
+}
+
+impl From<CborError> for Failure {
+    fn from(error: CborError) -> Self {
+        Failure::Input(error.to_string())
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Self {
+        Failure::Input(error.to_string())
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS
+    }
+    let result = parse_args(&args).and_then(|options| {
+        let input = read_input(options.file.as_deref())?;
+        run(&options, &input, &mut io::stdout().lock())
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Failure::Usage(message)) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            ExitCode::from(2)
+        },
+        Err(Failure::Input(message)) => {
+            eprintln!("error: {}", message);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, Failure> {
+    let mut args = args.iter();
+    let command = match args.next().map(String::as_str) {
+        Some("diag") => Command::Diag,
+        Some("annotate") => Command::Annotate,
+        Some("validate") => Command::Validate,
+        Some("to-json") => Command::ToJson,
+        Some("from-json") => Command::FromJson,
+        Some(other) => return Err(Failure::Usage(format!("unknown command {}", other))),
+        None => return Err(Failure::Usage("no command given".to_owned())),
+    };
+    let mut options = Options {
+        command,
+        input: Format::Raw,
+        output: Format::Raw,
+        pretty: false,
+        deterministic: false,
+        json: JsonOptions::default(),
+        file: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--input" => options.input = parse_format(args.next())?,
+            "-o" | "--output" => options.output = parse_format(args.next())?,
+            "-p" | "--pretty" => options.pretty = true,
+            "-d" | "--deterministic" => options.deterministic = true,
+            "--tags" => options.json.tags = match args.next().map(String::as_str) {
+                Some("content") => JsonTags::Content,
+                Some("object") => JsonTags::Object,
+                _ => return Err(Failure::Usage("--tags expects content or object".to_owned())),
+            },
+            "--bignums" => options.json.bignums = match args.next().map(String::as_str) {
+                Some("base64url") => JsonBignums::Base64url,
+                Some("decimal-string") => JsonBignums::DecimalString,
+                Some("number") => JsonBignums::Number,
+                _ => return Err(Failure::Usage("--bignums expects base64url, decimal-string or number".to_owned())),
+            },
+            flag if flag.starts_with('-') && flag != "-" => return Err(Failure::Usage(format!("unknown option {}", flag))),
+            file if options.file.is_none() => options.file = Some(file.to_owned()),
+            _ => return Err(Failure::Usage("more than one file given".to_owned())),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_format(arg: Option<&String>) -> Result<Format, Failure> {
+    match arg.map(String::as_str) {
+        Some("raw") => Ok(Format::Raw),
+        Some("hex") => Ok(Format::Hex),
+        Some("base64") => Ok(Format::Base64),
+        Some(other) => Err(Failure::Usage(format!("unknown format {}, expected raw, hex or base64", other))),
+        None => Err(Failure::Usage("missing format, expected raw, hex or base64".to_owned())),
+    }
+}
+
+fn read_input(file: Option<&str>) -> Result<Vec<u8>, Failure> {
+    match file {
+        None | Some("-") => {
+            let mut input = Vec::new();
+            io::stdin().lock().read_to_end(&mut input)?;
+            Ok(input)
+        },
+        Some(path) => fs::read(path).map_err(|e| Failure::Input(format!("{}: {}", path, e))),
+    }
+}
+
+/// Runs the command on the contents of the input file.
+fn run(options: &Options, input: &[u8], out: &mut impl Write) -> Result<(), Failure> {
+    if options.command == Command::FromJson {
+        let text = std::str::from_utf8(input).map_err(|_| Failure::Input("JSON input is not valid utf-8".to_owned()))?;
+        let bytes = json_to_cbor(text)?;
+        match options.output {
+            Format::Raw => out.write_all(&bytes)?,
+            Format::Hex => writeln!(out, "{}", to_hex(&bytes))?,
+            Format::Base64 => writeln!(out, "{}", encode_base64(&bytes, false))?,
+        }
+        return Ok(())
+    }
+    let bytes = decode_input(input, options.input)?;
+    match options.command {
+        Command::Diag if options.pretty => writeln!(out, "{}", to_diagnostic_pretty(&bytes)?)?,
+        Command::Diag => writeln!(out, "{}", to_diagnostic(&bytes)?)?,
+        Command::Annotate => {
+            out.write_all(annotate(&bytes).as_bytes())?;
+            // The dump shows where malformed input fails, but the exit status should too
+            let mut i = 0;
+            while i < bytes.len() {
+                i += validate(&bytes[i..]).map_err(|e| e.at(i))?;
+            }
+        },
+        Command::Validate => {
+            let len = validate(&bytes)?;
+            if len < bytes.len() {
+                return Err(Failure::Input(format!("{} trailing byte(s) after the data item at byte {}", bytes.len() - len, len)))
+            }
+            if options.deterministic {
+                check_deterministic(&bytes)?;
+                writeln!(out, "valid and deterministic")?;
+            } else {
+                writeln!(out, "valid")?;
+            }
+        },
+        Command::ToJson => writeln!(out, "{}", cbor_to_json(&bytes, &options.json)?)?,
+        Command::FromJson => unreachable!("handled above"),
+    }
+    Ok(())
+}
+
+/// Turns the input into CBOR bytes. Hex and base64 may be spread over several lines.
+fn decode_input(input: &[u8], format: Format) -> Result<Vec<u8>, Failure> {
+    let text = || std::str::from_utf8(input).map_err(|_| Failure::Input("input is not text".to_owned()));
+    match format {
+        Format::Raw => Ok(input.to_vec()),
+        Format::Hex => {
+            let digits: Vec<u8> = text()?.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+            if digits.len() % 2 == 1 {
+                return Err(Failure::Input("hex input has an odd number of digits".to_owned()))
+            }
+            digits.chunks(2)
+                .map(|pair| std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| Failure::Input("input is not valid hex".to_owned()))
+        },
+        Format::Base64 => decode_base64(text()?).ok_or_else(|| Failure::Input("input is not valid base64".to_owned())),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with(args: &[&str], input: &[u8]) -> Result<Vec<u8>, Failure> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut out = Vec::new();
+        run(&parse_args(&args)?, input, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_commands() {
+        let bytes = [0xa1, 0x61, b'a', 0x82, 0x01, 0x20];
+        assert_eq!(run_with(&["diag"], &bytes).unwrap(), b"{\"a\": [1, -1]}\n");
+        assert_eq!(run_with(&["diag", "--input", "hex"], b"a1 6161\n820120\n").unwrap(), b"{\"a\": [1, -1]}\n");
+        assert_eq!(run_with(&["diag", "-i", "base64"], b"oWFhggEg").unwrap(), b"{\"a\": [1, -1]}\n");
+        assert_eq!(run_with(&["diag", "-p"], &bytes).unwrap(), b"{\n  \"a\": [\n    1,\n    -1\n  ]\n}\n");
+        assert_eq!(run_with(&["annotate"], &[0x81, 0x01]).unwrap(), b"81    # array(1)\n   01 # unsigned(1)\n");
+        assert_eq!(run_with(&["validate"], &bytes).unwrap(), b"valid\n");
+        assert_eq!(run_with(&["validate", "-d"], &bytes).unwrap(), b"valid and deterministic\n");
+        assert_eq!(run_with(&["to-json"], &bytes).unwrap(), b"{\"a\":[1,-1]}\n");
+        let tagged = [0x82, 0xc1, 0x00, 0xc2, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(run_with(&["to-json"], &tagged).unwrap(), b"[0,\"AQAAAAAAAAAA\"]\n");
+        assert_eq!(
+            run_with(&["to-json", "--tags", "object", "--bignums", "number"], &tagged).unwrap(),
+            b"[{\"tag\":1,\"value\":0},18446744073709551616]\n",
+        );
+        assert_eq!(run_with(&["to-json", "--bignums", "decimal-string"], &tagged).unwrap(), b"[0,\"18446744073709551616\"]\n");
+        assert_eq!(run_with(&["from-json"], b"{\"a\": [1, -1]}").unwrap(), bytes);
+        assert_eq!(run_with(&["from-json", "-o", "hex"], b"[1.5, 100000.5]").unwrap(), b"82f93e00fa47c35040\n");
+        assert_eq!(run_with(&["from-json", "--output", "base64"], b"{\"a\": [1, -1]}").unwrap(), b"oWFhggEg\n");
+    }
+
+    #[test]
+    fn test_command_errors() {
+        assert!(matches!(run_with(&[], b""), Err(Failure::Usage(_))));
+        assert!(matches!(run_with(&["decode"], b""), Err(Failure::Usage(_))));
+        assert!(matches!(run_with(&["diag", "--input"], b""), Err(Failure::Usage(_))));
+        assert!(matches!(run_with(&["diag", "--input", "octal"], b""), Err(Failure::Usage(_))));
+        assert!(matches!(run_with(&["diag", "--verbose"], b""), Err(Failure::Usage(_))));
+        assert!(matches!(run_with(&["diag", "a", "b"], b""), Err(Failure::Usage(_))));
+        assert!(matches!(run_with(&["to-json", "--tags"], b""), Err(Failure::Usage(_))));
+        assert!(matches!(run_with(&["to-json", "--bignums", "hex"], b""), Err(Failure::Usage(_))));
+        assert_eq!(parse_args(&["diag".to_owned(), "-".to_owned()]).unwrap().file.as_deref(), Some("-"));
+
+        let Err(Failure::Input(message)) = run_with(&["validate"], &[0x82, 0x01]) else { panic!() };
+        assert_eq!(message, "unexpected end of input, 1 more byte(s) needed at byte 2");
+        let Err(Failure::Input(message)) = run_with(&["validate"], &[0x01, 0x02]) else { panic!() };
+        assert_eq!(message, "1 trailing byte(s) after the data item at byte 1");
+        let Err(Failure::Input(message)) = run_with(&["validate", "-d"], &[0x18, 0x01]) else { panic!() };
+        assert_eq!(message, "not deterministic: unsigned integer head is not in its shortest form at byte 0");
+        assert!(matches!(run_with(&["annotate"], &[0x82, 0x01]), Err(Failure::Input(_))));
+        assert!(matches!(run_with(&["diag", "-i", "hex"], b"a1f"), Err(Failure::Input(_))));
+        assert!(matches!(run_with(&["diag", "-i", "hex"], b"zz"), Err(Failure::Input(_))));
+        assert!(matches!(run_with(&["diag", "-i", "base64"], b"!!"), Err(Failure::Input(_))));
+        assert!(matches!(run_with(&["from-json"], b"{"), Err(Failure::Input(_))));
+    }
+}
@@ -0,0 +1,1032 @@
+//! Serde support, enabled by the `serde` feature.
+//!
+//! The serde data model is mapped onto CBOR the same way as the Cbor impls and the derive:
+//! - sequences, tuples and tuple structs are arrays, and maps and structs are maps
+//! - bytes (e.g. through `serde_bytes`) are byte strings, but `Vec<u8>` is an array of integers
+//! - `None`, `()` and unit structs are null, and `Some(value)` is just the value
+//! - newtype structs are arrays of one item, like derived tuple structs
+//! - enums are externally tagged like `#[cbor(repr = "map")]`: unit variants are a text string
+//!   and other variants a single-entry map from the variant name to the payload
+//!
+//! When deserializing, tags are skipped except for bignums (tags 2 and 3), which are read as
+//! 128-bit integers.
+
+use std::{borrow::Cow, fmt::Display, ops::Range};
+
+use ::serde::{de::{self, DeserializeSeed, IntoDeserializer, Visitor}, forward_to_deserialize_any, ser::{self, Serialize}, Deserialize};
+
+use crate::cbor::{
+    byte_at, bytes_from, describe_key, read_argument, read_byte_string, read_float, read_integer, read_length,
+    read_tag, read_text, write_head, Cbor, CborError, CborErrorKind, DecodeOptions, EncodeOptions, Limits, Value,
+};
+
+/// Serializes a value to CBOR.
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>, CborError> where T: Serialize + ?Sized {
+    to_vec_with(value, &EncodeOptions::default())
+}
+
+/// Serializes a value to CBOR with the given options.
+pub fn to_vec_with<T>(value: &T, options: &EncodeOptions) -> Result<Vec<u8>, CborError> where T: Serialize + ?Sized {
+    let mut bytes = Vec::new();
+    value.serialize(&mut Serializer::new(&mut bytes, *options))?;
+    Ok(bytes)
+}
+
+/// Deserializes `bytes` as exactly one data item, with `TrailingBytes` if anything follows it.
+pub fn from_slice<'de, T>(bytes: &'de [u8]) -> Result<T, CborError> where T: Deserialize<'de> {
+    from_slice_with(bytes, &DecodeOptions::default())
+}
+
+/// Like `from_slice`, within the given options. Every string counts against `max_allocation`,
+/// even one that is borrowed, as the deserializer can't tell whether it gets copied. Sequences
+/// and maps don't count, as their item types are not known here.
+pub fn from_slice_with<'de, T>(bytes: &'de [u8], options: &DecodeOptions) -> Result<T, CborError> where T: Deserialize<'de> {
+    let mut deserializer = Deserializer::with_options(bytes, options);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+impl ser::Error for CborError {
+    fn custom<T: Display>(msg: T) -> Self {
+        CborErrorKind::Unexpected(msg.to_string()).into()
+    }
+}
+
+impl de::Error for CborError {
+    fn custom<T: Display>(msg: T) -> Self {
+        CborErrorKind::Unexpected(msg.to_string()).into()
+    }
+}
+
+/// Appends the CBOR encoding of serialized values to a Vec.
+pub struct Serializer<'a> {
+    bytes: &'a mut Vec<u8>,
+    options: EncodeOptions,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(bytes: &'a mut Vec<u8>, options: EncodeOptions) -> Self {
+        Serializer { bytes, options }
+    }
+
+    fn write_text(&mut self, text: &str) {
+        write_head(self.bytes, 3, text.len() as u64);
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    /// Starts a map, which is sorted and written at the end if the options ask for deterministic output.
+    fn start_map(&mut self, len: Option<usize>) -> Compound<'_, 'a> {
+        let state = match len {
+            _ if self.options.deterministic => State::Sorted { entries: Vec::new(), key: Vec::new() },
+            Some(len) => {
+                write_head(self.bytes, 5, len as u64);
+                State::Definite
+            },
+            None => {
+                self.bytes.push(0xbf);
+                State::Indefinite
+            },
+        };
+        Compound { ser: self, state }
+    }
+}
+
+/// An array, map or struct being serialized.
+pub struct Compound<'b, 'a> {
+    ser: &'b mut Serializer<'a>,
+    state: State,
+}
+
+enum State {
+    Definite,
+    /// Needs a "break" at the end.
+    Indefinite,
+    /// Map entries encoded on their own, to be sorted by key at the end. `key` is the pending key.
+    Sorted { entries: Vec<(Vec<u8>, Vec<u8>)>, key: Vec<u8> },
+    /// Items of a sequence of unknown length, encoded on their own to be written after a
+    /// definite-length head at the end.
+    Counted { items: Vec<u8>, len: usize },
+}
+
+impl Compound<'_, '_> {
+    fn element<T>(&mut self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        match &mut self.state {
+            State::Counted { items, len } => {
+                value.serialize(&mut Serializer::new(items, self.ser.options))?;
+                *len += 1;
+                Ok(())
+            },
+            _ => value.serialize(&mut *self.ser),
+        }
+    }
+
+    fn key<T>(&mut self, key: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        match &mut self.state {
+            State::Sorted { key: pending, .. } => {
+                *pending = to_vec_with(key, &self.ser.options)?;
+                Ok(())
+            },
+            _ => key.serialize(&mut *self.ser),
+        }
+    }
+
+    fn value<T>(&mut self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        match &mut self.state {
+            State::Sorted { entries, key } => {
+                entries.push((std::mem::take(key), to_vec_with(value, &self.ser.options)?));
+                Ok(())
+            },
+            _ => value.serialize(&mut *self.ser),
+        }
+    }
+
+    fn field<T>(&mut self, name: &'static str, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.key(name)?;
+        self.value(value)
+    }
+
+    fn finish(self) -> Result<(), CborError> {
+        match self.state {
+            State::Definite => (),
+            State::Indefinite => self.ser.bytes.push(0xff),
+            State::Sorted { mut entries, .. } => {
+                entries.sort_unstable();
+                write_head(self.ser.bytes, 5, entries.len() as u64);
+                for (key, value) in entries {
+                    self.ser.bytes.extend_from_slice(&key);
+                    self.ser.bytes.extend_from_slice(&value);
+                }
+            },
+            State::Counted { items, len } => {
+                write_head(self.ser.bytes, 4, len as u64);
+                self.ser.bytes.extend_from_slice(&items);
+            },
+        }
+        Ok(())
+    }
+}
+
+impl<'b, 'a> ser::Serializer for &'b mut Serializer<'a> {
+    type Ok = ();
+    type Error = CborError;
+    type SerializeSeq = Compound<'b, 'a>;
+    type SerializeTuple = Compound<'b, 'a>;
+    type SerializeTupleStruct = Compound<'b, 'a>;
+    type SerializeTupleVariant = Compound<'b, 'a>;
+    type SerializeMap = Compound<'b, 'a>;
+    type SerializeStruct = Compound<'b, 'a>;
+    type SerializeStructVariant = Compound<'b, 'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), CborError> {
+        v.encode_into(self.bytes, &self.options);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), CborError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), CborError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), CborError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), CborError> {
+        v.encode_into(self.bytes, &self.options);
+        Ok(())
+    }
+
+    /// Integers outside the 64-bit range are written as bignums (tags 2 and 3).
+    fn serialize_i128(self, v: i128) -> Result<(), CborError> {
+        let (major_type, argument) = if v < 0 { (1, !v as u128) } else { (0, v as u128) };
+        match u64::try_from(argument) {
+            Ok(argument) => write_head(self.bytes, major_type, argument),
+            Err(_) => {
+                let content = argument.to_be_bytes();
+                let content = &content[argument.leading_zeros() as usize / 8..];
+                write_head(self.bytes, 6, 2 + major_type as u64);
+                write_head(self.bytes, 2, content.len() as u64);
+                self.bytes.extend_from_slice(content);
+            },
+        }
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), CborError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), CborError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), CborError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), CborError> {
+        write_head(self.bytes, 0, v);
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), CborError> {
+        match i128::try_from(v) {
+            Ok(v) => self.serialize_i128(v),
+            Err(_) => {
+                write_head(self.bytes, 6, 2);
+                write_head(self.bytes, 2, 16);
+                self.bytes.extend_from_slice(&v.to_be_bytes());
+                Ok(())
+            },
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), CborError> {
+        v.encode_into(self.bytes, &self.options);
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), CborError> {
+        v.encode_into(self.bytes, &self.options);
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), CborError> {
+        self.write_text(v.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), CborError> {
+        self.write_text(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), CborError> {
+        write_head(self.bytes, 2, v.len() as u64);
+        self.bytes.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), CborError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), CborError> {
+        self.bytes.push(0xf6);
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), CborError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str) -> Result<(), CborError> {
+        self.write_text(variant);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        write_head(self.bytes, 4, 1);
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(self, _name: &'static str, _variant_index: u32, variant: &'static str, value: &T) -> Result<(), CborError>
+        where T: Serialize + ?Sized
+    {
+        write_head(self.bytes, 5, 1);
+        self.write_text(variant);
+        value.serialize(self)
+    }
+
+    /// A sequence of unknown length has indefinite length, unless the options ask for
+    /// deterministic output.
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'b, 'a>, CborError> {
+        let state = match len {
+            Some(len) => {
+                write_head(self.bytes, 4, len as u64);
+                State::Definite
+            },
+            None if self.options.deterministic => State::Counted { items: Vec::new(), len: 0 },
+            None => {
+                self.bytes.push(0x9f);
+                State::Indefinite
+            },
+        };
+        Ok(Compound { ser: self, state })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'b, 'a>, CborError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'b, 'a>, CborError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str, len: usize) -> Result<Compound<'b, 'a>, CborError> {
+        write_head(self.bytes, 5, 1);
+        self.write_text(variant);
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'b, 'a>, CborError> {
+        Ok(self.start_map(len))
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'b, 'a>, CborError> {
+        Ok(self.start_map(Some(len)))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _variant_index: u32, variant: &'static str, len: usize) -> Result<Compound<'b, 'a>, CborError> {
+        write_head(self.bytes, 5, 1);
+        self.write_text(variant);
+        Ok(self.start_map(Some(len)))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_, '_> {
+    type Ok = ();
+    type Error = CborError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), CborError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_, '_> {
+    type Ok = ();
+    type Error = CborError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), CborError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_, '_> {
+    type Ok = ();
+    type Error = CborError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), CborError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_, '_> {
+    type Ok = ();
+    type Error = CborError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), CborError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for Compound<'_, '_> {
+    type Ok = ();
+    type Error = CborError;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.key(key)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.value(value)
+    }
+
+    fn end(self) -> Result<(), CborError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_, '_> {
+    type Ok = ();
+    type Error = CborError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), CborError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_, '_> {
+    type Ok = ();
+    type Error = CborError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), CborError> where T: Serialize + ?Sized {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), CborError> {
+        self.finish()
+    }
+}
+
+/// Deserializes values from a byte slice, borrowing strings and bytes from it where the
+/// deserialized type allows. Error offsets count from the start of the slice.
+pub struct Deserializer<'de> {
+    bytes: &'de [u8],
+    position: usize,
+    limits: Limits,
+}
+
+impl<'de> Deserializer<'de> {
+    pub fn from_slice(bytes: &'de [u8]) -> Self {
+        Deserializer::with_options(bytes, &DecodeOptions::default())
+    }
+
+    pub fn with_options(bytes: &'de [u8], options: &DecodeOptions) -> Self {
+        Deserializer { bytes, position: 0, limits: Limits::new(*options) }
+    }
+
+    /// Checks that the whole input has been deserialized.
+    pub fn end(&self) -> Result<(), CborError> {
+        match self.bytes.len() - self.position {
+            0 => Ok(()),
+            trailing => Err(self.error(CborErrorKind::TrailingBytes(trailing))),
+        }
+    }
+
+    fn rest(&self) -> Result<&'de [u8], CborError> {
+        bytes_from(self.bytes, self.position)
+    }
+
+    fn peek(&self) -> Result<u8, CborError> {
+        byte_at(self.bytes, self.position)
+    }
+
+    fn error(&self, kind: CborErrorKind) -> CborError {
+        CborError::new(kind).at(self.position)
+    }
+
+    /// Runs a reader from the cbor module on the rest of the input and moves past what it read.
+    fn read<T>(&mut self, read: impl FnOnce(&'de [u8]) -> Result<(T, usize), CborError>) -> Result<T, CborError> {
+        let (value, bytes_read) = read(self.rest()?).map_err(|e| e.at(self.position))?;
+        self.position += bytes_read;
+        Ok(value)
+    }
+
+    /// Reads a byte or text string and checks it against the limits. It counts against
+    /// `max_allocation` even if it is borrowed, as the visitor may still copy it.
+    fn read_string<S>(&mut self, read: impl FnOnce(&'de [u8]) -> Result<(Cow<'de, S>, usize), CborError>) -> Result<Cow<'de, S>, CborError>
+        where S: ToOwned + AsRef<[u8]> + ?Sized
+    {
+        let start = self.position;
+        let string = self.read(read)?;
+        let len = AsRef::<[u8]>::as_ref(&*string).len();
+        self.limits.check_string_len(len).map_err(|e| e.at(start))?;
+        self.limits.allocate(len).map_err(|e| e.at(start))?;
+        Ok(string)
+    }
+
+    /// Reads the head of an array or map of the given major type and checks its length against the limits.
+    /// Returns the number of items or entries, or None if it has indefinite length.
+    fn read_container_head(&mut self, major_type: u8) -> Result<Option<usize>, CborError> {
+        let initial_byte = self.peek()?;
+        if initial_byte == (major_type << 5) | 0x1f {
+            self.position += 1;
+            return Ok(None)
+        }
+        let start = self.position;
+        let len = self.read(read_length)?;
+        self.limits.check_collection_len(len).map_err(|e| e.at(start))?;
+        Ok(Some(len))
+    }
+
+    /// Runs `deserialize` one level deeper, with `DepthExceeded` if that is past the limit.
+    fn nested<T>(&mut self, deserialize: impl FnOnce(&mut Self) -> Result<T, CborError>) -> Result<T, CborError> {
+        self.limits.enter().map_err(|e| e.at(self.position))?;
+        let result = deserialize(self);
+        self.limits.leave();
+        result
+    }
+
+    /// Checks that another item of an indefinite-length array or map, at `index`, is within the limits.
+    fn check_indefinite_len(&self, index: usize) -> Result<(), CborError> {
+        self.limits.check_collection_len(index + 1).map_err(|e| e.at(self.position))
+    }
+
+    /// Checks that an array or map was read to its end, and moves past the "break" if it has indefinite length.
+    fn finish_container(&mut self, remaining: Option<usize>) -> Result<(), CborError> {
+        match remaining {
+            Some(0) => Ok(()),
+            Some(remaining) => Err(self.error(CborErrorKind::Unexpected(format!("{} more items than expected", remaining)))),
+            None if self.peek()? == 0xff => {
+                self.position += 1;
+                Ok(())
+            },
+            None => Err(self.error(CborErrorKind::Unexpected("More items than expected".to_owned()))),
+        }
+    }
+
+    /// Reads the byte string content of a bignum as an unsigned 128-bit integer.
+    fn read_bignum(&mut self) -> Result<u128, CborError> {
+        let start = self.position;
+        let content = self.read_string(read_byte_string)?;
+        if content.iter().skip_while(|&&byte| byte == 0).count() > 16 {
+            return Err(CborError::new(CborErrorKind::Unexpected("Bignum does not fit in 128 bits".to_owned())).at(start))
+        }
+        Ok(content.iter().fold(0, |num, &byte| num << 8 | byte as u128))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = CborError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        let initial_byte = self.peek()?;
+        match initial_byte >> 5 {
+            0 => {
+                let num = self.read(read_argument)?;
+                visitor.visit_u64(num)
+            },
+            1 => {
+                let num = self.read(read_integer)?;
+                match i64::try_from(num) {
+                    Ok(num) => visitor.visit_i64(num),
+                    Err(_) => visitor.visit_i128(num),
+                }
+            },
+            2 => match self.read_string(read_byte_string)? {
+                Cow::Borrowed(content) => visitor.visit_borrowed_bytes(content),
+                Cow::Owned(content) => visitor.visit_byte_buf(content),
+            },
+            3 => match self.read_string(read_text)? {
+                Cow::Borrowed(text) => visitor.visit_borrowed_str(text),
+                Cow::Owned(text) => visitor.visit_string(text),
+            },
+            4 => {
+                let len = self.read_container_head(4)?;
+                self.nested(|de| {
+                    let mut access = SeqAccess { de, remaining: len, index: 0 };
+                    let value = visitor.visit_seq(&mut access)?;
+                    let remaining = access.remaining;
+                    de.finish_container(remaining)?;
+                    Ok(value)
+                })
+            },
+            5 => {
+                let len = self.read_container_head(5)?;
+                self.nested(|de| {
+                    let mut access = MapAccess { de, remaining: len, index: 0, key: 0..0 };
+                    let value = visitor.visit_map(&mut access)?;
+                    let remaining = access.remaining;
+                    de.finish_container(remaining)?;
+                    Ok(value)
+                })
+            },
+            6 => {
+                let tag = self.read(read_tag)?;
+                let is_bytes = self.peek()? >> 5 == 2;
+                match tag {
+                    2 if is_bytes => visitor.visit_u128(self.read_bignum()?),
+                    3 if is_bytes => {
+                        let start = self.position;
+                        match i128::try_from(self.read_bignum()?) {
+                            Ok(num) => visitor.visit_i128(-1 - num),
+                            Err(_) => Err(CborError::new(CborErrorKind::Unexpected("Bignum does not fit in 128 bits".to_owned())).at(start)),
+                        }
+                    },
+                    _ => self.nested(|de| de.deserialize_any(visitor)),
+                }
+            },
+            _ => match initial_byte {
+                0xf4 | 0xf5 => {
+                    self.position += 1;
+                    visitor.visit_bool(initial_byte == 0xf5)
+                },
+                0xf6 | 0xf7 => {
+                    self.position += 1;
+                    visitor.visit_unit()
+                },
+                0xfa => {
+                    let float = self.read(read_float)?;
+                    visitor.visit_f32(float as f32)
+                },
+                0xf9 | 0xfb => {
+                    let float = self.read(read_float)?;
+                    visitor.visit_f64(float)
+                },
+                0xff => Err(self.error(CborErrorKind::IllFormed("\"break\" outside of an indefinite length item".to_owned()))),
+                _ => Err(self.error(CborErrorKind::Unexpected(format!("Simple value {:x} has no serde equivalent", initial_byte)))),
+            },
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        match self.peek()? {
+            0xf6 | 0xf7 => {
+                self.position += 1;
+                visitor.visit_none()
+            },
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        match self.peek()? {
+            0xf6 | 0xf7 => {
+                self.position += 1;
+                visitor.visit_unit()
+            },
+            found => Err(self.error(CborErrorKind::TypeMismatch { expected: "null", found })),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        let initial_byte = self.peek()?;
+        if initial_byte >> 5 != 4 {
+            return Err(self.error(CborErrorKind::TypeMismatch { expected: "array of one item", found: initial_byte }))
+        }
+        let len = self.read_container_head(4)?;
+        if len.is_some_and(|len| len != 1) {
+            return Err(self.error(CborErrorKind::Unexpected(format!("Expected an array of 1 item. Got {}", len.unwrap_or_default()))))
+        }
+        self.nested(|de| {
+            let value = visitor.visit_newtype_struct(&mut *de).map_err(|e| e.in_index(0))?;
+            de.finish_container(len.map(|_| 0))?;
+            Ok(value)
+        })
+    }
+
+    fn deserialize_enum<V>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, CborError>
+        where V: Visitor<'de>
+    {
+        let initial_byte = self.peek()?;
+        match initial_byte >> 5 {
+            3 => {
+                let name = self.read_string(read_text)?;
+                visitor.visit_enum(name.as_ref().into_deserializer())
+            },
+            5 => {
+                let len = self.read_container_head(5)?;
+                if len.is_some_and(|len| len != 1) {
+                    return Err(self.error(CborErrorKind::Unexpected(format!(
+                        "Expected a map with a single variant. Got {} entries", len.unwrap_or_default()
+                    ))))
+                }
+                self.nested(|de| {
+                    let value = visitor.visit_enum(EnumAccess { de: &mut *de, name: 0..0 })?;
+                    de.finish_container(len.map(|_| 0))?;
+                    Ok(value)
+                })
+            },
+            _ => Err(self.error(CborErrorKind::TypeMismatch { expected: "text string or map", found: initial_byte })),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        let (_, bytes_read) = <Value as Cbor>::from_cbor_bytes_with(self.rest()?, &mut self.limits)
+            .map_err(|e| e.at(self.position))?;
+        self.position += bytes_read;
+        visitor.visit_unit()
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf seq tuple tuple_struct map struct identifier
+    }
+}
+
+struct SeqAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    /// Items left in a definite-length array, or None for an indefinite-length one.
+    remaining: Option<usize>,
+    index: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for &mut SeqAccess<'_, 'de> {
+    type Error = CborError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, CborError> where T: DeserializeSeed<'de> {
+        match self.remaining {
+            Some(0) => return Ok(None),
+            Some(remaining) => self.remaining = Some(remaining - 1),
+            None if self.de.peek()? == 0xff => return Ok(None),
+            None => self.de.check_indefinite_len(self.index)?,
+        }
+        let element = seed.deserialize(&mut *self.de).map_err(|e| e.in_index(self.index))?;
+        self.index += 1;
+        Ok(Some(element))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+struct MapAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    /// Entries left in a definite-length map, or None for an indefinite-length one.
+    remaining: Option<usize>,
+    index: usize,
+    /// Where the key of the current entry is in the input, for error paths.
+    key: Range<usize>,
+}
+
+impl<'de> de::MapAccess<'de> for &mut MapAccess<'_, 'de> {
+    type Error = CborError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, CborError> where K: DeserializeSeed<'de> {
+        match self.remaining {
+            Some(0) => return Ok(None),
+            Some(remaining) => self.remaining = Some(remaining - 1),
+            None if self.de.peek()? == 0xff => return Ok(None),
+            None => self.de.check_indefinite_len(self.index)?,
+        }
+        let start = self.de.position;
+        let key = seed.deserialize(&mut *self.de).map_err(|e| e.in_index(self.index))?;
+        self.key = start..self.de.position;
+        self.index += 1;
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, CborError> where V: DeserializeSeed<'de> {
+        seed.deserialize(&mut *self.de).map_err(|e| e.in_key(describe_key(&self.de.bytes[self.key.clone()])))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+}
+
+/// A variant written as a single-entry map, after the map head.
+struct EnumAccess<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    /// Where the variant name is in the input, for error paths.
+    name: Range<usize>,
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for EnumAccess<'a, 'de> {
+    type Error = CborError;
+    type Variant = Self;
+
+    fn variant_seed<V>(mut self, seed: V) -> Result<(V::Value, Self), CborError> where V: DeserializeSeed<'de> {
+        let start = self.de.position;
+        let variant = seed.deserialize(&mut *self.de).map_err(|e| e.in_index(0))?;
+        self.name = start..self.de.position;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> EnumAccess<'_, 'de> {
+    fn payload<T>(self, deserialize: impl FnOnce(&mut Deserializer<'de>) -> Result<T, CborError>) -> Result<T, CborError> {
+        let name = describe_key(&self.de.bytes[self.name]);
+        deserialize(self.de).map_err(|e| e.in_key(name))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumAccess<'_, 'de> {
+    type Error = CborError;
+
+    fn unit_variant(self) -> Result<(), CborError> {
+        self.payload(|de| <() as Deserialize>::deserialize(de))
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, CborError> where T: DeserializeSeed<'de> {
+        self.payload(|de| seed.deserialize(de))
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        self.payload(|de| de::Deserializer::deserialize_seq(de, visitor))
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, CborError> where V: Visitor<'de> {
+        self.payload(|de| de::Deserializer::deserialize_map(de, visitor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ::serde::{Deserialize, Serialize};
+
+    use crate::cbor::{check_deterministic, decode_cbor, PathSegment, Tagged};
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Reading<'a> {
+        sensor: &'a str,
+        values: Vec<i32>,
+        #[serde(with = "bytes")]
+        raw: Vec<u8>,
+        unit: Option<Unit>,
+        shape: Shape,
+        id: Id,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Unit {
+        Celsius,
+        Kelvin,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Circle(f64),
+        Rect { w: u8, h: u8 },
+        Line(u8, u8),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Id(u64);
+
+    /// Stand-in for serde_bytes.
+    mod bytes {
+        pub fn serialize<S: ::serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+
+        pub fn deserialize<'de, D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+            struct BytesVisitor;
+            impl<'de> ::serde::de::Visitor<'de> for BytesVisitor {
+                type Value = Vec<u8>;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("byte string")
+                }
+
+                fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                    Ok(v.to_vec())
+                }
+            }
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let reading = Reading {
+            sensor: "temp",
+            values: vec![-1, 1000],
+            raw: vec![0xde, 0xad],
+            unit: Some(Unit::Kelvin),
+            shape: Shape::Rect { w: 1, h: 2 },
+            id: Id(7),
+        };
+        let bytes = to_vec(&reading).unwrap();
+        assert_eq!(from_slice::<Reading>(&bytes).unwrap(), reading);
+
+        let value: Value = decode_cbor(&bytes).unwrap();
+        assert_eq!(value.get("sensor"), Some(&Value::Text("temp".to_owned())));
+        assert_eq!(value.get("raw"), Some(&Value::Bytes(vec![0xde, 0xad])));
+        assert_eq!(value.get("unit"), Some(&Value::Text("Kelvin".to_owned())));
+        assert_eq!(value.get("id"), Some(&Value::Array(vec![Value::Unsigned(7)])));
+        let rect = Value::Map(vec![(Value::Text("w".to_owned()), Value::Unsigned(1)), (Value::Text("h".to_owned()), Value::Unsigned(2))]);
+        assert_eq!(value.get("shape"), Some(&Value::Map(vec![(Value::Text("Rect".to_owned()), rect)])));
+
+        for shape in [Shape::Circle(1.5), Shape::Line(3, 4)] {
+            assert_eq!(from_slice::<Shape>(&to_vec(&shape).unwrap()).unwrap(), shape);
+        }
+        assert_eq!(to_vec(&Shape::Line(3, 4)).unwrap(), vec![0xa1, 0x64, b'L', b'i', b'n', b'e', 0x82, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn test_serde_matches_cbor_impls() {
+        let mut map = BTreeMap::new();
+        map.insert("a".to_owned(), vec![1.5f64, -2.0]);
+        map.insert("bb".to_owned(), vec![]);
+        assert_eq!(to_vec(&map).unwrap(), map.to_cbor_bytes());
+        assert_eq!(from_slice::<BTreeMap<String, Vec<f64>>>(&map.to_cbor_bytes()).unwrap(), map);
+
+        let numbers = vec![0u64, 23, 24, 255, 256, u64::MAX];
+        assert_eq!(to_vec(&numbers).unwrap(), numbers.to_cbor_bytes());
+        let negative = vec![-1i64, -24, -25, i64::MIN];
+        assert_eq!(to_vec(&negative).unwrap(), negative.to_cbor_bytes());
+        assert_eq!(from_slice::<Vec<i64>>(&negative.to_cbor_bytes()).unwrap(), negative);
+
+        let shortest = EncodeOptions::deterministic();
+        let mut map = std::collections::HashMap::new();
+        map.insert(300u16, 1.0f32);
+        map.insert(2u16, 0.5f32);
+        let bytes = to_vec_with(&map, &shortest).unwrap();
+        assert_eq!(bytes, map.to_cbor_bytes_with(&shortest));
+        assert_eq!(check_deterministic(&bytes), Ok(()));
+
+        // Sequences of unknown length get a definite length when deterministic
+        struct Evens(u8);
+        impl Serialize for Evens {
+            fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq((0..self.0).filter(|num| num % 2 == 0))
+            }
+        }
+        assert_eq!(to_vec(&Evens(5)).unwrap(), vec![0x9f, 0x00, 0x02, 0x04, 0xff]);
+        let bytes = to_vec_with(&[Evens(5), Evens(0)], &shortest).unwrap();
+        assert_eq!(bytes, vec![0x82, 0x83, 0x00, 0x02, 0x04, 0x80]);
+        assert_eq!(check_deterministic(&bytes), Ok(()));
+    }
+
+    #[test]
+    fn test_serde_special_values() {
+        // 128-bit integers beyond 64 bits are bignums
+        for num in [u64::MAX as i128 + 1, i128::MAX, -(u64::MAX as i128) - 2, i128::MIN] {
+            let bytes = to_vec(&num).unwrap();
+            assert_eq!(bytes[0], if num < 0 { 0xc3 } else { 0xc2 });
+            assert_eq!(from_slice::<i128>(&bytes).unwrap(), num);
+        }
+        assert_eq!(from_slice::<u128>(&to_vec(&u128::MAX).unwrap()).unwrap(), u128::MAX);
+        assert_eq!(to_vec(&(u64::MAX as u128 + 1)).unwrap(), vec![0xc2, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Other tags are skipped, and indefinite lengths are accepted
+        assert_eq!(from_slice::<String>(&Tagged::new(32, "x".to_owned()).to_cbor_bytes()).unwrap(), "x");
+        assert_eq!(from_slice::<Vec<u8>>(&[0x9f, 0x01, 0x02, 0xff]).unwrap(), vec![1, 2]);
+        assert_eq!(from_slice::<String>(&[0x7f, 0x61, b'a', 0x61, b'b', 0xff]).unwrap(), "ab");
+        assert_eq!(from_slice::<Option<u8>>(&[0xf6]).unwrap(), None);
+        assert_eq!(from_slice::<(u8, bool)>(&[0x82, 0x01, 0xf5]).unwrap(), (1, true));
+        assert_eq!(from_slice::<f32>(&[0xf9, 0x3e, 0x00]).unwrap(), 1.5);
+    }
+
+    #[test]
+    fn test_serde_errors() {
+        let error = from_slice::<u8>(&[0x01, 0x02]).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::TrailingBytes(1));
+        assert_eq!(error.offset, 1);
+
+        let error = from_slice::<(u8, u8)>(&[0x83, 0x01, 0x02, 0x03]).unwrap_err();
+        assert_eq!(error.offset, 3);
+
+        let mut map = BTreeMap::new();
+        map.insert("key".to_owned(), vec!["a".to_owned()]);
+        let mut bytes = map.to_cbor_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] = 0xff;
+        let error = from_slice::<BTreeMap<String, Vec<String>>>(&bytes).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::InvalidUtf8);
+        assert_eq!(error.offset, last);
+        assert_eq!(error.path, vec![PathSegment::Key("key".to_owned()), PathSegment::Index(0)]);
+
+        let error = from_slice::<Shape>(&[0xa1, 0x64, b'L', b'i', b'n', b'e', 0x81, 0x03]).unwrap_err();
+        assert_eq!(error.path, vec![PathSegment::Key("Line".to_owned())]);
+        assert!(from_slice::<Unit>(&[0x65, b'H', b'o', b'u', b'r', b's']).is_err());
+    }
+
+    #[test]
+    fn test_serde_limits() {
+        let mut nested = vec![0x81; 100_000];
+        nested.push(0x00);
+        let error = from_slice::<::serde::de::IgnoredAny>(&nested).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::DepthExceeded(128));
+        let mut tags = vec![0xc6; 100_000];
+        tags.push(0x00);
+        assert_eq!(from_slice::<u8>(&tags).unwrap_err().kind, CborErrorKind::DepthExceeded(128));
+
+        let options = DecodeOptions { max_depth: 2, max_string_len: 3, max_collection_len: 2, ..Default::default() };
+        assert_eq!(from_slice_with::<Vec<Vec<u8>>>(&[0x81, 0x80], &options).unwrap(), vec![Vec::<u8>::new()]);
+        assert_eq!(from_slice_with::<Vec<Vec<Vec<u8>>>>(&[0x81, 0x81, 0x80], &options).unwrap_err().kind, CborErrorKind::DepthExceeded(2));
+        let error = from_slice_with::<Vec<String>>(&[0x82, 0x61, b'a', 0x64, b'a', b'b', b'c', b'd'], &options).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::LengthTooLarge(4));
+        assert_eq!(error.offset, 3);
+        assert_eq!(error.path, vec![PathSegment::Index(1)]);
+        assert_eq!(from_slice_with::<Vec<u8>>(&[0x83, 0x01, 0x02, 0x03], &options).unwrap_err().kind, CborErrorKind::LengthTooLarge(3));
+        let error = from_slice_with::<Vec<u8>>(&[0x9f, 0x01, 0x02, 0x03, 0xff], &options).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::LengthTooLarge(3));
+        assert_eq!(error.offset, 3);
+
+        let options = DecodeOptions { max_allocation: 5, ..Default::default() };
+        assert!(from_slice_with::<(String, String)>(&[0x82, 0x62, b'a', b'b', 0x63, b'c', b'd', b'e'], &options).is_ok());
+        let error = from_slice_with::<(String, String)>(&[0x82, 0x62, b'a', b'b', 0x64, b'c', b'd', b'e', b'f'], &options).unwrap_err();
+        assert_eq!((error.kind, error.offset), (CborErrorKind::AllocationExceeded(5), 4));
+        assert!(from_slice_with::<(&str, &str)>(&[0x82, 0x62, b'a', b'b', 0x64, b'c', b'd', b'e', b'f'], &options).is_err());
+        let error = from_slice_with::<(String, String)>(&[0x82, 0x7f, 0x62, b'a', b'b', 0xff, 0x7f, 0x64, b'c', b'd', b'e', b'f', 0xff], &options);
+        assert_eq!(error.unwrap_err().kind, CborErrorKind::AllocationExceeded(5));
+    }
+}
@@ -0,0 +1,462 @@
+use std::{io::{Read, Write}, marker::PhantomData};
+
+use crate::cbor::{bytes_from, read_argument, Cbor, CborError, CborErrorKind, DecodeOptions, EncodeOptions, Limits};
+
+/// Limits on what a `CborReader` will buffer for a single data item. How deeply the item may
+/// nest is the `max_depth` of the `DecodeOptions` it is read with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Largest encoded size of one data item, in bytes.
+    pub max_item_size: usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits { max_item_size: 16 * 1024 * 1024 }
+    }
+}
+
+/// Decodes data items from a `std::io::Read` one at a time.
+///
+/// Each item is collected in an internal buffer, up to `ReadLimits::max_item_size`, and then
+/// decoded. The reader never reads ahead of the item, so whatever follows it stays in the source
+/// for other code to read, such as the rest of a protocol after a CBOR handshake. Reads are
+/// therefore small: wrap unbuffered sources such as files and sockets in a `std::io::BufReader`,
+/// whose `buffer` then holds the bytes read ahead.
+pub struct CborReader<R> {
+    reader: R,
+    limits: ReadLimits,
+    buffer: Vec<u8>,
+    position: u64,
+}
+
+impl<R: Read> CborReader<R> {
+    pub fn new(reader: R) -> Self {
+        CborReader::with_limits(reader, ReadLimits::default())
+    }
+
+    pub fn with_limits(reader: R, limits: ReadLimits) -> Self {
+        CborReader { reader, limits, buffer: Vec::new(), position: 0 }
+    }
+
+    /// Reads and decodes the next data item.
+    /// Error offsets count from the first byte this reader read.
+    pub fn decode<T>(&mut self) -> Result<T, CborError> where T: Cbor {
+        self.decode_with(&DecodeOptions::default())
+    }
+
+    /// Like `decode`, within the given options.
+    pub fn decode_with<T>(&mut self, options: &DecodeOptions) -> Result<T, CborError> where T: Cbor {
+        let start = self.position;
+        let item = self.read_item_bytes_with(options)?;
+        let (value, _) = T::from_cbor_bytes_with(item, &mut Limits::new(*options)).map_err(|e| e.at(start as usize))?;
+        Ok(value)
+    }
+
+    /// Like `decode`, but returns None if the reader is at its end before the item starts.
+    pub fn try_decode<T>(&mut self) -> Result<Option<T>, CborError> where T: Cbor {
+        self.try_decode_with(&DecodeOptions::default())
+    }
+
+    /// Like `try_decode`, within the given options.
+    pub fn try_decode_with<T>(&mut self, options: &DecodeOptions) -> Result<Option<T>, CborError> where T: Cbor {
+        let start = self.position;
+        match self.try_read_item_bytes_with(options)? {
+            Some(item) => {
+                let (value, _) = T::from_cbor_bytes_with(item, &mut Limits::new(*options)).map_err(|e| e.at(start as usize))?;
+                Ok(Some(value))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the encoded bytes of the next data item without decoding it.
+    /// The item is only checked for being complete, not for being well-formed throughout.
+    pub fn read_item_bytes(&mut self) -> Result<&[u8], CborError> {
+        self.read_item_bytes_with(&DecodeOptions::default())
+    }
+
+    /// Like `read_item_bytes`, with `DepthExceeded` if the item nests deeper than `options.max_depth`.
+    pub fn read_item_bytes_with(&mut self, options: &DecodeOptions) -> Result<&[u8], CborError> {
+        self.buffer.clear();
+        let result = self.read_item(0, options.max_depth);
+        self.position += self.buffer.len() as u64;
+        result?;
+        Ok(&self.buffer)
+    }
+
+    /// Like `read_item_bytes`, but returns None if the reader is at its end before the item starts.
+    pub fn try_read_item_bytes(&mut self) -> Result<Option<&[u8]>, CborError> {
+        self.try_read_item_bytes_with(&DecodeOptions::default())
+    }
+
+    /// Like `try_read_item_bytes`, with `DepthExceeded` if the item nests deeper than `options.max_depth`.
+    pub fn try_read_item_bytes_with(&mut self, options: &DecodeOptions) -> Result<Option<&[u8]>, CborError> {
+        self.buffer.clear();
+        let read = (&mut self.reader).take(1).read_to_end(&mut self.buffer)
+            .map_err(|e| CborError::from(e).at(self.offset()))?;
+        if read == 0 {
+            return Ok(None)
+        }
+        let result = self.read_item_after_initial_byte(0, 0, options.max_depth);
+        self.position += self.buffer.len() as u64;
+        result?;
+        Ok(Some(&self.buffer))
+    }
+
+    /// Total number of bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns the source. Nothing after the last item read has been taken from it.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Appends one data item at `depth` to the buffer. Items may nest down to `max_depth`.
+    fn read_item(&mut self, depth: usize, max_depth: usize) -> Result<(), CborError> {
+        let start = self.buffer.len();
+        self.fill(1)?;
+        self.read_item_after_initial_byte(start, depth, max_depth)
+    }
+
+    /// Appends the rest of a data item whose initial byte is already in the buffer at `start`.
+    fn read_item_after_initial_byte(&mut self, start: usize, depth: usize, max_depth: usize) -> Result<(), CborError> {
+        if depth > max_depth {
+            return Err(self.error(CborErrorKind::DepthExceeded(max_depth)))
+        }
+        let initial_byte = self.buffer[start];
+        let major_type = initial_byte >> 5;
+        let argument = match initial_byte & 0x1f {
+            0x00..0x18 => initial_byte as u64 & 0x1f,
+            info @ 0x18..0x1c => {
+                self.fill(1 << (info - 0x18))?;
+                read_argument(&self.buffer[start..])?.0
+            },
+            0x1f if matches!(major_type, 2..=5) => return self.read_indefinite(major_type, depth, max_depth),
+            _ => return Err(self.error(CborErrorKind::IllFormed(format!("Unexpected initial byte {:x}", initial_byte)))),
+        };
+        match major_type {
+            2 | 3 => match usize::try_from(argument) {
+                Ok(len) => self.fill(len),
+                Err(_) => Err(self.error(CborErrorKind::LengthTooLarge(argument))),
+            },
+            4 => (0..argument).try_for_each(|_| self.read_item(depth + 1, max_depth)),
+            5 => (0..argument).try_for_each(|_| {
+                self.read_item(depth + 1, max_depth)?;
+                self.read_item(depth + 1, max_depth)
+            }),
+            6 => self.read_item(depth + 1, max_depth),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends the items of an indefinite-length string, array or map, up to and including the "break".
+    fn read_indefinite(&mut self, major_type: u8, depth: usize, max_depth: usize) -> Result<(), CborError> {
+        let mut items = 0usize;
+        loop {
+            let start = self.buffer.len();
+            self.fill(1)?;
+            if self.buffer[start] == 0xff {
+                break
+            }
+            self.read_item_after_initial_byte(start, depth + 1, max_depth)?;
+            items += 1;
+        }
+        if major_type == 5 && !items.is_multiple_of(2) {
+            return Err(self.error(CborErrorKind::IllFormed("Map is missing the value of its last entry".to_owned())))
+        }
+        Ok(())
+    }
+
+    /// Reads exactly `len` more bytes into the buffer.
+    fn fill(&mut self, len: usize) -> Result<(), CborError> {
+        let size = self.buffer.len().saturating_add(len);
+        if size > self.limits.max_item_size {
+            return Err(self.error(CborErrorKind::LengthTooLarge(size as u64)))
+        }
+        // read_to_end grows the buffer as data arrives, so a bogus length can't allocate up front
+        let read = (&mut self.reader).take(len as u64).read_to_end(&mut self.buffer)
+            .map_err(|e| CborError::from(e).at(self.offset()))?;
+        if read < len {
+            return Err(self.error(CborErrorKind::UnexpectedEnd { needed: len - read }))
+        }
+        Ok(())
+    }
+
+    /// Offset of the end of the buffer from the first byte this reader read.
+    fn offset(&self) -> usize {
+        (self.position + self.buffer.len() as u64) as usize
+    }
+
+    fn error(&self, kind: CborErrorKind) -> CborError {
+        CborError::new(kind).at(self.offset())
+    }
+}
+
+/// Decodes one data item from a reader, leaving any bytes after it unread.
+pub fn decode_cbor_from_reader<T, R>(reader: R) -> Result<T, CborError>
+where
+    T: Cbor,
+    R: Read,
+{
+    CborReader::new(reader).decode()
+}
+
+/// Like `decode_cbor_from_reader`, within the given options.
+pub fn decode_cbor_from_reader_with<T, R>(reader: R, options: &DecodeOptions) -> Result<T, CborError>
+where
+    T: Cbor,
+    R: Read,
+{
+    CborReader::new(reader).decode_with(options)
+}
+
+/// Iterates over the data items of a CBOR sequence (RFC 8742), the concatenation of zero or
+/// more items. The source is a byte slice or a `CborReader`.
+///
+/// Each item is decoded as `T`, which can be `Value` when the items have no fixed type.
+/// The `DecodeOptions` apply to each item on its own. Iteration stops after the first error.
+pub struct CborSequence<S, T> {
+    source: S,
+    options: DecodeOptions,
+    position: usize,
+    failed: bool,
+    item: PhantomData<T>,
+}
+
+impl<'a, T> CborSequence<&'a [u8], T> where T: Cbor {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        CborSequence::with_options(bytes, &DecodeOptions::default())
+    }
+
+    pub fn with_options(bytes: &'a [u8], options: &DecodeOptions) -> Self {
+        CborSequence { source: bytes, options: *options, position: 0, failed: false, item: PhantomData }
+    }
+
+    /// The bytes that have not been decoded yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.source.get(self.position..).unwrap_or_default()
+    }
+}
+
+impl<T> Iterator for CborSequence<&[u8], T> where T: Cbor {
+    type Item = Result<T, CborError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.position >= self.source.len() {
+            return None
+        }
+        let result = bytes_from(self.source, self.position)
+            .and_then(|bytes| T::from_cbor_bytes_with(bytes, &mut Limits::new(self.options)))
+            .map_err(|e| e.at(self.position));
+        match result {
+            Ok((item, bytes_read)) => {
+                self.position += bytes_read;
+                Some(Ok(item))
+            },
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+impl<R, T> CborSequence<CborReader<R>, T> where R: Read, T: Cbor {
+    pub fn from_reader(reader: R) -> Self {
+        CborSequence::from_cbor_reader(CborReader::new(reader))
+    }
+
+    pub fn from_reader_with(reader: R, options: &DecodeOptions) -> Self {
+        CborSequence::from_cbor_reader_with(CborReader::new(reader), options)
+    }
+
+    pub fn from_cbor_reader(reader: CborReader<R>) -> Self {
+        CborSequence::from_cbor_reader_with(reader, &DecodeOptions::default())
+    }
+
+    pub fn from_cbor_reader_with(reader: CborReader<R>, options: &DecodeOptions) -> Self {
+        CborSequence { source: reader, options: *options, position: 0, failed: false, item: PhantomData }
+    }
+
+    pub fn into_inner(self) -> CborReader<R> {
+        self.source
+    }
+}
+
+impl<R, T> Iterator for CborSequence<CborReader<R>, T> where R: Read, T: Cbor {
+    type Item = Result<T, CborError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None
+        }
+        let result = self.source.try_decode_with(&self.options).transpose();
+        self.failed = matches!(result, Some(Err(_)));
+        result
+    }
+}
+
+/// Writes a CBOR sequence (RFC 8742) by appending one data item after another.
+pub struct CborSequenceWriter<W> {
+    writer: W,
+    options: EncodeOptions,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> CborSequenceWriter<W> {
+    pub fn new(writer: W) -> Self {
+        CborSequenceWriter::with_options(writer, EncodeOptions::default())
+    }
+
+    pub fn with_options(writer: W, options: EncodeOptions) -> Self {
+        CborSequenceWriter { writer, options, buffer: Vec::new() }
+    }
+
+    /// Appends one item. The item is written with a single `write_all`, so it is not split
+    /// across writes to the underlying writer.
+    pub fn write<T>(&mut self, item: &T) -> std::io::Result<()> where T: Cbor {
+        self.buffer.clear();
+        item.encode_into(&mut self.buffer, &self.options);
+        self.writer.write_all(&self.buffer)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::cbor::{Tagged, Value};
+
+    use super::*;
+
+    #[test]
+    fn test_reader_leaves_rest_unread() {
+        let mut input = vec![0x13u32, 1000].to_cbor_bytes();
+        input.extend_from_slice(&"rest".to_owned().to_cbor_bytes());
+        input.extend_from_slice(&[0x9f, 0x01, 0x5f, 0x41, 0x00, 0xff, 0xbf, 0x61, b'k', 0xc1, 0x00, 0xff, 0xff]);
+        input.push(0xaa);
+        let mut cursor = Cursor::new(input);
+
+        let numbers: Vec<u32> = decode_cbor_from_reader(&mut cursor).unwrap();
+        assert_eq!(numbers, vec![0x13, 1000]);
+        assert_eq!(cursor.position(), 5);
+
+        let mut reader = CborReader::new(&mut cursor);
+        assert_eq!(reader.decode::<String>().unwrap(), "rest");
+        let value: Value = reader.decode().unwrap();
+        assert_eq!(value, Value::Array(vec![
+            Value::Unsigned(1),
+            Value::Bytes(vec![0]),
+            Value::Map(vec![(Value::Text("k".to_owned()), Value::Tag(1, Box::new(Value::Unsigned(0))))]),
+        ]));
+        assert_eq!(reader.bytes_read(), 18);
+        assert_eq!(cursor.position(), 23);
+    }
+
+    #[test]
+    fn test_reader_errors() {
+        // Truncated input reports the stream offset where it ran out
+        let mut reader = CborReader::new(Cursor::new(vec![0x01, 0x83, 0x01, 0x62, b'a']));
+        assert_eq!(reader.decode::<u8>().unwrap(), 1);
+        let error = reader.decode::<Value>().unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::UnexpectedEnd { needed: 1 });
+        assert_eq!(error.offset, 5);
+
+        // Decoding errors are offset to the item's position in the stream
+        let mut reader = CborReader::new(Cursor::new(vec![0x00, 0x82, 0x01, 0x61, b'a']));
+        reader.decode::<u8>().unwrap();
+        let error = reader.decode::<Vec<u8>>().unwrap_err();
+        assert_eq!(error.offset, 3);
+
+        let limits = ReadLimits { max_item_size: 8 };
+        let mut reader = CborReader::with_limits(Cursor::new(vec![0x5a, 0xff, 0xff, 0xff, 0xff]), limits);
+        assert!(matches!(reader.decode::<Value>().unwrap_err().kind, CborErrorKind::LengthTooLarge(_)));
+
+        let options = DecodeOptions { max_depth: 3, ..Default::default() };
+        let nested = Tagged::new(1, Tagged::new(2, vec![0u8])).to_cbor_bytes();
+        assert!(CborReader::new(Cursor::new(nested.clone())).decode_with::<Value>(&options).is_ok());
+        let mut deeper = vec![0x81];
+        deeper.extend_from_slice(&nested);
+        let error = CborReader::new(Cursor::new(deeper.clone())).decode_with::<Value>(&options).unwrap_err();
+        assert_eq!(error.kind, CborErrorKind::DepthExceeded(3));
+        assert!(CborReader::new(Cursor::new(deeper)).read_item_bytes_with(&options).is_err());
+
+        // The other options apply to the decode, with offsets in the stream
+        let options = DecodeOptions { max_string_len: 2, ..Default::default() };
+        let mut reader = CborReader::new(Cursor::new(vec![0x61, b'a', 0x82, 0x00, 0x63, b'a', b'b', b'c']));
+        assert_eq!(reader.decode_with::<String>(&options).unwrap(), "a");
+        let error = reader.decode_with::<Value>(&options).unwrap_err();
+        assert_eq!((error.kind, error.offset), (CborErrorKind::LengthTooLarge(3), 4));
+        let error = decode_cbor_from_reader_with::<Vec<u8>, _>(Cursor::new(vec![0x81, 0x81, 0x00]), &DecodeOptions { max_depth: 1, ..Default::default() });
+        assert_eq!(error.unwrap_err().kind, CborErrorKind::DepthExceeded(1));
+
+        let mut reader = CborReader::new(Cursor::new(vec![0xbf, 0x01, 0xff]));
+        assert!(matches!(reader.decode::<Value>().unwrap_err().kind, CborErrorKind::IllFormed(_)));
+        let mut reader = CborReader::new(Cursor::new(vec![0xff]));
+        assert!(matches!(reader.decode::<Value>().unwrap_err().kind, CborErrorKind::IllFormed(_)));
+    }
+
+    #[test]
+    fn test_sequence() {
+        let mut writer = CborSequenceWriter::new(Vec::new());
+        writer.write(&1u8).unwrap();
+        writer.write(&vec![2u8, 3]).unwrap();
+        writer.write(&"four".to_owned()).unwrap();
+        let log = writer.into_inner();
+        assert_eq!(log, vec![0x01, 0x82, 0x02, 0x03, 0x64, b'f', b'o', b'u', b'r']);
+
+        let values: Vec<Value> = CborSequence::new(&log).collect::<Result<_, _>>().unwrap();
+        assert_eq!(values, vec![
+            Value::Unsigned(1),
+            Value::Array(vec![Value::Unsigned(2), Value::Unsigned(3)]),
+            Value::Text("four".to_owned()),
+        ]);
+        let from_reader: Vec<Value> = CborSequence::from_reader(Cursor::new(&log)).collect::<Result<_, _>>().unwrap();
+        assert_eq!(from_reader, values);
+        assert_eq!(CborSequence::<_, Value>::new(&[]).count(), 0);
+        assert_eq!(CborSequence::<_, Value>::from_reader(Cursor::new(vec![])).count(), 0);
+
+        // Items of one type, with the remaining bytes available after an error
+        let mut numbers = CborSequence::<_, u16>::new(&log);
+        assert_eq!(numbers.next().unwrap().unwrap(), 1);
+        let error = numbers.next().unwrap().unwrap_err();
+        assert_eq!(error.offset, 1);
+        assert!(numbers.next().is_none());
+        assert_eq!(numbers.remaining(), &log[1..]);
+
+        // A truncated last item is an error in both sources
+        let truncated = &log[..log.len() - 1];
+        let errors: Vec<_> = CborSequence::<_, Value>::new(truncated).filter_map(Result::err).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, CborErrorKind::UnexpectedEnd { needed: 1 });
+        let errors: Vec<_> = CborSequence::<_, Value>::from_reader(Cursor::new(truncated)).filter_map(Result::err).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].offset, truncated.len());
+
+        // Options apply to every item
+        let options = DecodeOptions { max_collection_len: 1, ..Default::default() };
+        let error = CborSequence::<_, Value>::with_options(&log, &options).find_map(Result::err).unwrap();
+        assert_eq!((error.kind, error.offset), (CborErrorKind::LengthTooLarge(2), 1));
+        let error = CborSequence::<_, Value>::from_reader_with(Cursor::new(&log), &options).find_map(Result::err).unwrap();
+        assert_eq!((error.kind, error.offset), (CborErrorKind::LengthTooLarge(2), 1));
+    }
+}